@@ -1,5 +1,8 @@
 use bevy_math::DVec3;
 
+pub const GRAVITATIONAL_CONSTANT: f64 = 6.6743e-11; // m^3 / (kg s^2), CODATA 2018
+pub const AU: f64 = 1.495978707e11; // Meters, IAU 2012
+
 // Define all bodies in ICRF
 
 pub fn planets_info() -> Vec<(crate::BodyState, crate::BodyInfo)> {
@@ -12,7 +15,8 @@ pub fn planets_info() -> Vec<(crate::BodyState, crate::BodyInfo)> {
                 mu: 1.327124400189e20,
                 radius: 695700e3,
                 j2: 0.0,
-                rotational_rate: 0.0,
+                rotational_rate: 0.0000028653,
+                prime_meridian: 84.176f64.to_radians(),
                 tilt: DVec3::new(0.0, -23.5f64.to_radians().sin(), 23.5f64.to_radians().cos()),
                 affected: false,
                 affects: true,
@@ -38,6 +42,7 @@ pub fn planets_info() -> Vec<(crate::BodyState, crate::BodyInfo)> {
                 radius: 2440.53e3,
                 j2: 50.3e-6,
                 rotational_rate: 0.00000124001,
+                prime_meridian: 329.5988f64.to_radians(),
                 tilt: DVec3::new(0.089,-0.461,0.875),
                 affected: true,
                 affects: true,
@@ -63,6 +68,7 @@ pub fn planets_info() -> Vec<(crate::BodyState, crate::BodyInfo)> {
                 radius: 6051.893e3,
                 j2: 4.458e-6,
                 rotational_rate: -0.00000029924,
+                prime_meridian: 160.20f64.to_radians(),
                 tilt: DVec3::new(-0.019,-0.388,0.921),
                 affected: true,
                 affects: true,
@@ -88,6 +94,7 @@ pub fn planets_info() -> Vec<(crate::BodyState, crate::BodyInfo)> {
                 radius: 6378.137e3,
                 j2: 1082.63e-6,
                 rotational_rate: 0.00007292115,
+                prime_meridian: 190.147f64.to_radians(),
                 tilt: DVec3::Z, // By convention ICRF
                 affected: true,
                 affects: true,
@@ -113,6 +120,7 @@ pub fn planets_info() -> Vec<(crate::BodyState, crate::BodyInfo)> {
                 radius: 3396.19e3,
                 j2: 1960.45e-6,
                 rotational_rate: 0.0000708822,
+                prime_meridian: 176.049863f64.to_radians(),
                 tilt: DVec3::new(0.445,-0.406,0.798),
                 affected: true,
                 affects: true,
@@ -138,6 +146,7 @@ pub fn planets_info() -> Vec<(crate::BodyState, crate::BodyInfo)> {
                 radius: 66854e3,
                 j2: 14736e-6,
                 rotational_rate: 0.00017585,
+                prime_meridian: 284.95f64.to_radians(),
                 tilt: DVec3::new(0.015,-0.434,0.901),
                 affected: true,
                 affects: true,
@@ -163,6 +172,7 @@ pub fn planets_info() -> Vec<(crate::BodyState, crate::BodyInfo)> {
                 radius: 54364e3,
                 j2: 16298e-6,
                 rotational_rate: 0.000163785,
+                prime_meridian: 38.90f64.to_radians(),
                 tilt: DVec3::new(0.085,0.073,0.994),
                 affected: true,
                 affects: true,
@@ -188,6 +198,7 @@ pub fn planets_info() -> Vec<(crate::BodyState, crate::BodyInfo)> {
                 radius: 25559e3,
                 j2: 3343.43e-6,
                 rotational_rate: -0.000101237,
+                prime_meridian: 203.81f64.to_radians(),
                 tilt: DVec3::new(-0.214,-0.940,-0.262),
                 affected: true,
                 affects: true,
@@ -213,6 +224,7 @@ pub fn planets_info() -> Vec<(crate::BodyState, crate::BodyInfo)> {
                 radius: 24766e3,
                 j2: 3411e-6,
                 rotational_rate: 0.000108338,
+                prime_meridian: 249.978f64.to_radians(),
                 tilt: DVec3::new(0.369,-0.622,0.689), // nearly zero obliquity
                 affected: true,
                 affects: true,
@@ -240,6 +252,7 @@ pub fn planets_info() -> Vec<(crate::BodyState, crate::BodyInfo)> {
                 radius: 1738.0e3,
                 j2: 202.7e-6,
                 rotational_rate: 0.0000026617,
+                prime_meridian: 38.3213f64.to_radians(),
                 tilt: DVec3::new(0.0,-0.395,0.918),
                 affected: true,
                 affects: true,
@@ -264,7 +277,8 @@ pub fn planets_info() -> Vec<(crate::BodyState, crate::BodyInfo)> {
                 mu: 7.11e5,
                 radius: 13.1e3,
                 j2: 0.0,
-                rotational_rate: 0.0,
+                rotational_rate: 0.000228035,
+                prime_meridian: 35.06f64.to_radians(),
                 tilt: DVec3::new(0.445,-0.406,0.798),
                 affected: true,
                 affects: true,
@@ -289,7 +303,8 @@ pub fn planets_info() -> Vec<(crate::BodyState, crate::BodyInfo)> {
                 mu: 8.53e4,
                 radius: 13.1e3,
                 j2: 0.0,
-                rotational_rate: 0.0,
+                rotational_rate: 0.0000576042,
+                prime_meridian: 79.41f64.to_radians(),
                 tilt: DVec3::new(0.445,-0.406,0.798),
                 affected: true,
                 affects: true,
@@ -1,11 +1,29 @@
 use std::slice::Windows;
 use bevy::input::keyboard::KeyboardInput;
-use bevy::input::mouse::{MouseButtonInput, MouseScrollUnit, MouseWheel};
+use bevy::input::mouse::{MouseButtonInput, MouseMotion, MouseScrollUnit, MouseWheel};
 use bevy::prelude::*;
 use bevy::transform;
-use bevy::window::PrimaryWindow;
 use crate::*;
-use chrono::{DateTime, Duration, Local};
+use crate::bodies_init::AU;
+use crate::equinoctial::Elements;
+use crate::frames::Frame;
+use crate::time::{step_to_epoch, TimeScale};
+use crate::spheres::sphere_of_influence;
+use crate::transfer::spacecraft_display_position;
+// The camera orbits the focused body (or, chasing, the spacecraft) at pan/tilt/dist, or flies freely, looking along
+// pan/tilt. Changing focus or mode eases the camera over from where it was instead of cutting.
+
+const FLY_SECONDS: f64 = 1.5;
+const DRAG_SPEED: f64 = 0.005; // Radians per pixel
+const MAX_DISTANCE: f64 = 100.0 * AU; // For bodies without a parent
+const CHASE_DISTANCE: (f64, f64) = (1.0e3, 1.0e9);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CameraMode {
+    Orbit,
+    FreeFly,
+    Chase, // Orbit the spacecraft, while the selected transfer is in flight
+}
 
 #[derive(Component)]
 pub struct CameraState {
@@ -13,59 +31,131 @@ pub struct CameraState {
     pub tilt: f64,
     pub dist: f64,
     pub focused: u32,
+    pub frame: Frame, // Orients the camera, and the axes the OE overlay is given in
+    pub mode: CameraMode,
+    pub position: DVec3, // Where the camera was put last frame, in the simulation's frame
+    pub flight: Option<(DVec3, f64)>, // Where an eased move started, and how far along it is (0 to 1)
+    pub rotation: Quat, // The camera's orientation last frame
+}
+
+impl CameraState {
+    // Ease over to wherever the camera's headed next, starting from where it is now
+    pub fn fly(&mut self) {
+        self.flight = Some((self.position, 0.0));
+    }
+}
+
+// Where the camera goes to orbit target at pan/tilt/dist, pan measured from the axes' x about their z, and the
+// direction it's put in from the target (it looks back down that). Kept in f64 all the way to the grid, so the camera
+// lands as precisely as big_space can place it however far the target is from the origin.
+pub fn orbit_placement(axes: DMat3, pan: f64, tilt: f64, dist: f64, target: DVec3) -> (DVec3, DVec3) {
+    let up = axes.z_axis;
+    let reference = axes.x_axis;
+    let horizontal = (reference - up * reference.dot(up)).normalize();
+    let perpendicular = up.cross(horizontal);
+    let forward = (horizontal * (tilt.cos() * pan.cos()) + perpendicular * (tilt.cos() * pan.sin()) + up * tilt.sin()).normalize();
+    (target + forward * dist, forward)
+}
+
+// Where a position in the simulation's frame shows up on screen. The projection works from its offset to the camera,
+// taken in f64, rather than from f32 world positions.
+pub fn viewport_position(camera: &Camera, camera_state: &CameraState, position: DVec3) -> Option<Vec2> {
+    let view = GlobalTransform::from(Transform::from_rotation(camera_state.rotation));
+    camera.world_to_viewport(&view, (position - camera_state.position).as_vec3()).ok()
+}
+
+// How close and how far the camera may orbit a body: outside its surface, and near enough to still take in its
+// parent's sphere of influence
+fn distance_limits(state_keeper: &StateKeeper, body: u32) -> (f64, f64) {
+    let info = state_keeper.info.get(&body).unwrap();
+    let parent = info.kepler_parent;
+    if parent == body {
+        return (info.radius * 1.8, MAX_DISTANCE);
+    }
+    let states = state_keeper.state.get(&state_keeper.current_step).unwrap();
+    let to_parent = (states.get(&body).unwrap()[0] - states.get(&parent).unwrap()[0]).length();
+    let max = (sphere_of_influence(state_keeper, parent, state_keeper.current_step) + to_parent).min(MAX_DISTANCE);
+    (info.radius * 1.8, max.max(info.radius * 1.8))
+}
+
+// The frame after the current one in the cycle of frames centered on (or about) the focused body
+fn next_frame(state_keeper: &StateKeeper, frame: Frame, focused: u32) -> Frame {
+    let parent = state_keeper.info.get(&focused).unwrap().kepler_parent;
+    let mut frames = vec![Frame::BodyInertial(focused), Frame::BodyFixed(focused), Frame::EclipticJ2000, Frame::Icrf];
+    if parent != focused {
+        frames.push(Frame::Synodic(parent, focused));
+    }
+    let index = frames.iter().position(|f| *f == frame).map_or(0, |i| i + 1);
+    frames[index % frames.len()]
+}
+
+// Center the camera, and the scene, on a body, and orbit it
+pub fn focus_on(state_keeper: &mut StateKeeper, camera_state: &mut CameraState, body: u32) {
+    state_keeper.inertial = body;
+    camera_state.focused = body;
+    camera_state.frame = camera_state.frame.recentered(state_keeper, body);
+    camera_state.mode = CameraMode::Orbit;
+    camera_state.fly();
 }
 
 pub fn camera_controller(
     state_keeper: Res<StateKeeper>,
-    camera: Single<(&Camera, &GlobalTransform, &mut CameraState)>,
+    camera: Single<(&Camera, &mut CameraState)>,
     camera_grid: Single<(&mut GridCell<i64>, &mut Transform), With<FloatingOrigin>>,
     root_grid: Single<&Grid<i64>, With<BigSpace>>,
 
     keys: Res<ButtonInput<KeyCode>>,
     time: Res<Time>,
     mut evr_scroll: EventReader<MouseWheel>,
+    mut evr_motion: EventReader<MouseMotion>,
     mouse_input: Res<ButtonInput<MouseButton>>,
 
     mut query1: Query<(&BodyOverlayDisplay, &mut Node, &Text), Without<TextOverlay>>,
-    query2: Query<(&BodyDisplay, &ObjectID, &GlobalTransform)>,
     mut query3: Query<(&Node, &mut Text, &TextOverlay), (With<TextOverlay>, Without<BodyOverlayDisplay>)>,
-    q_windows: Query<&Window, With<PrimaryWindow>>,
 ) {
-    let (camera, camera_global_transform, mut camera_state) = camera.into_inner();
+    let (camera, mut camera_state) = camera.into_inner();
     let root_grid = root_grid.into_inner();
 
-    let mut abs_offset = state_keeper.state.get(&state_keeper.current_step).unwrap().get(&camera_state.focused).unwrap()[0];
-
     let speed = 1.0;
 
-    if mouse_input.just_pressed(MouseButton::Left) {
-        if let Some(position) = q_windows.single().cursor_position() {
-            let pos_x = q_windows.single().width() - position.x;
-            let pos_y = position.y;
-            if pos_x < 1105.0 && pos_x > 5.0 {
-                info!("{}", pos_x);
-            }
-        }
+    if keys.just_pressed(KeyCode::KeyF) {
+        camera_state.frame = next_frame(&state_keeper, camera_state.frame, camera_state.focused);
     }
 
-    if keys.pressed(KeyCode::KeyW) {
-        camera_state.tilt = camera_state.tilt + speed * time.delta_secs_f64();
-        if camera_state.tilt > 89.9f64.to_radians() {
-            camera_state.tilt = 89.9f64.to_radians();
-        }
-    }
-    if keys.pressed(KeyCode::KeyS) {
-        camera_state.tilt = camera_state.tilt - speed * time.delta_secs_f64();
-        if camera_state.tilt < -89.9f64.to_radians() {
-            camera_state.tilt = -89.9f64.to_radians();
+    // C cycles orbiting, flying freely, and chasing the spacecraft (when there's a transfer to chase)
+    if keys.just_pressed(KeyCode::KeyC) {
+        camera_state.mode = match camera_state.mode {
+            CameraMode::Orbit => CameraMode::FreeFly,
+            CameraMode::FreeFly if state_keeper.interplanetary.is_some() => CameraMode::Chase,
+            _ => CameraMode::Orbit,
+        };
+        if camera_state.mode != CameraMode::FreeFly {
+            camera_state.fly();
         }
     }
-    if keys.pressed(KeyCode::KeyA) {
-        camera_state.pan = camera_state.pan - speed * time.delta_secs_f64();
+
+    // Dragging with the right button orbits the camera, or looks around when flying freely
+    let drag: Vec2 = evr_motion.read().map(|ev| ev.delta).sum();
+    if mouse_input.pressed(MouseButton::Right) {
+        camera_state.pan = camera_state.pan - drag.x as f64 * DRAG_SPEED;
+        camera_state.tilt = camera_state.tilt + drag.y as f64 * DRAG_SPEED;
     }
-    if keys.pressed(KeyCode::KeyD) {
-        camera_state.pan = camera_state.pan + speed * time.delta_secs_f64();
+
+    if camera_state.mode != CameraMode::FreeFly {
+        if keys.pressed(KeyCode::KeyW) {
+            camera_state.tilt = camera_state.tilt + speed * time.delta_secs_f64();
+        }
+        if keys.pressed(KeyCode::KeyS) {
+            camera_state.tilt = camera_state.tilt - speed * time.delta_secs_f64();
+        }
+        if keys.pressed(KeyCode::KeyA) {
+            camera_state.pan = camera_state.pan - speed * time.delta_secs_f64();
+        }
+        if keys.pressed(KeyCode::KeyD) {
+            camera_state.pan = camera_state.pan + speed * time.delta_secs_f64();
+        }
     }
+    camera_state.tilt = camera_state.tilt.clamp(-89.9f64.to_radians(), 89.9f64.to_radians());
 
     for ev in evr_scroll.read() {
         match ev.unit {
@@ -76,82 +166,134 @@ pub fn camera_controller(
         }
     }
 
-    // Ensure that the camera doesn't end up at absurd distances (too close to the focus, or too far)
-    if camera_state.dist < state_keeper.info.get(&camera_state.focused).unwrap().radius * 1.8 {
-        camera_state.dist = state_keeper.info.get(&camera_state.focused).unwrap().radius * 1.8
-    } else if camera_state.dist > state_keeper.info.get(&camera_state.focused).unwrap().radius.powf(1.5) {
-        camera_state.dist = state_keeper.info.get(&camera_state.focused).unwrap().radius.powf(1.5)
-    }
-
-    // Camera Pan/Tilt
-    let provided_up: Vec3 = state_keeper.info.get(&camera_state.focused).unwrap().tilt.as_vec3();
-    let reference: Vec3 = if provided_up.abs_diff_eq(Vec3::X, 1e-6) {
-        Vec3::Y
-    } else {
-        Vec3::X
+    // What the camera orbits, and how near and far it may be from it
+    let chased = spacecraft_display_position(&state_keeper).filter(|_| camera_state.mode == CameraMode::Chase);
+    let abs_offset = match chased {
+        Some(position) => position,
+        None => display_position(&state_keeper, state_keeper.current_step, state_keeper.state.get(&state_keeper.current_step).unwrap().get(&camera_state.focused).unwrap()[0]),
+    };
+    let (min_dist, max_dist) = match chased {
+        Some(_) => CHASE_DISTANCE,
+        None => distance_limits(&state_keeper, camera_state.focused),
     };
+    camera_state.dist = camera_state.dist.clamp(min_dist, max_dist);
 
-    let horizontal = (reference - provided_up * reference.dot(provided_up)).normalize();
-    let perpendicular = provided_up.cross(horizontal);
+    // Camera Pan/Tilt, relative to the axes of the chosen frame
+    let axes = display_rotation(&state_keeper) * camera_state.frame.at(&state_keeper, state_keeper.current_step).rotation;
+    let provided_up = axes.z_axis;
+    let (mut camera_position, mut forward) = orbit_placement(axes, camera_state.pan, camera_state.tilt, camera_state.dist, abs_offset);
 
-    let forward = (horizontal * (camera_state.tilt.cos() * camera_state.pan.cos()) as f32)
-        + (perpendicular * (camera_state.tilt.cos() * camera_state.pan.sin()) as f32)
-        + (provided_up * camera_state.tilt.sin() as f32);
-    let forward = forward.normalize();
+    // Flying freely, WASD moves along the view (the camera looks down -forward), and space and shift up and down, at
+    // a speed that scales with the distance to the focused body
+    if camera_state.mode == CameraMode::FreeFly {
+        let right = provided_up.cross(forward).normalize();
+        let mut direction = DVec3::ZERO;
+        for (key, towards) in [
+            (KeyCode::KeyW, -forward),
+            (KeyCode::KeyS, forward),
+            (KeyCode::KeyA, -right),
+            (KeyCode::KeyD, right),
+            (KeyCode::Space, provided_up),
+            (KeyCode::ShiftLeft, -provided_up),
+        ] {
+            if keys.pressed(key) {
+                direction += towards;
+            }
+        }
+        let fly_speed = (camera_state.position - abs_offset).length().max(min_dist);
+        camera_position = camera_state.position + direction.normalize_or_zero() * fly_speed * time.delta_secs_f64();
+        // Stopped at min_dist from the focused body rather than flying into it
+        let from_body = camera_position - abs_offset;
+        if from_body.length() < min_dist {
+            camera_position = abs_offset + from_body.normalize_or(forward) * min_dist;
+        }
+    }
 
-    let offset = forward * camera_state.dist as f32;
-    let camera_x = offset.x;
-    let camera_y = offset.y;
-    let camera_z = offset.z;
+    // Ease in and out of a move, looking at where the camera's headed the whole way
+    if let Some((from, progress)) = camera_state.flight {
+        let progress = (progress + time.delta_secs_f64() / FLY_SECONDS).min(1.0);
+        let eased = progress * progress * (3.0 - 2.0 * progress);
+        camera_position = from.lerp(camera_position, eased);
+        camera_state.flight = if progress < 1.0 { Some((from, progress)) } else { None };
+        if progress < 1.0 {
+            forward = (camera_position - abs_offset).normalize_or(forward);
+        }
+    }
 
-    let (new_camera_grid_gridcell, new_camera_grid_transform) = root_grid.translation_to_grid(
-        DVec3::new((camera_x + abs_offset.x as f32) as f64, (camera_y + abs_offset.y as f32) as f64, (camera_z + abs_offset.z as f32) as f64)
-    );
+    let right = provided_up.cross(forward).normalize();
+    let up = forward.cross(right);
+    camera_state.position = camera_position;
+    camera_state.rotation = Quat::from_mat3(&DMat3::from_cols(right, up, forward).as_mat3());
 
+    let (new_camera_grid_gridcell, new_camera_grid_transform) = root_grid.translation_to_grid(camera_position);
     let (mut camera_grid_gridcell, mut camera_grid_transform) = camera_grid.into_inner();
-    camera_grid_gridcell.x = new_camera_grid_gridcell.x;
-    camera_grid_gridcell.y = new_camera_grid_gridcell.y;
-    camera_grid_gridcell.z = new_camera_grid_gridcell.z;
+    *camera_grid_gridcell = new_camera_grid_gridcell;
     camera_grid_transform.translation = new_camera_grid_transform;
-
-    let right = provided_up.cross(forward).normalize();
-    let up = forward.cross(right);
-    let rotation_matrix = Mat3::from_cols(right, up, forward);
-    camera_grid_transform.rotation = Quat::from_mat3(&rotation_matrix);
+    camera_grid_transform.rotation = camera_state.rotation;
     // End Camera Pan/Tilt
 
-    // // Update planet labels to match the position of the planets
-    for (_body_display, body_object_id, body_global_transform) in query2.iter() {
-        let (_body_overlay_display, mut node, mut _text) = query1.get_mut(state_keeper.info.get(&body_object_id.id).unwrap().body_overlay_display_id.unwrap()).unwrap();
-        let world_position = body_global_transform.translation();
-        if body_object_id.id == state_keeper.inertial || state_keeper.info.get(&body_object_id.id).unwrap().kepler_parent == state_keeper.inertial {
-            if let Ok(viewport_pos) = camera.world_to_viewport(camera_global_transform, world_position) {
+    // Update planet labels to match the position of the planets
+    for (id, info) in state_keeper.info.iter() {
+        let (_body_overlay_display, mut node, _text) = query1.get_mut(info.body_overlay_display_id.unwrap()).unwrap();
+        let position = display_position(&state_keeper, state_keeper.current_step, state_keeper.state.get(&state_keeper.current_step).unwrap().get(id).unwrap()[0]);
+        match viewport_position(camera, &camera_state, position).filter(|_| body_shown(&state_keeper, *id)) {
+            Some(viewport_pos) => {
                 node.display = Display::DEFAULT;
                 node.top = Val::Px(viewport_pos.y);
                 node.left = Val::Px(viewport_pos.x);
-            } else {
-                node.display = Display::None;
             }
-        } else {
-            node.display = Display::None;
+            None => node.display = Display::None,
         }
     }
 
     // Display OE
     for (node, mut text, text_overlay) in query3.iter_mut() {
         if text_overlay.id == 0 {
-            let t0 = DateTime::parse_from_str("2025 Apr 01 12:00:00 +0000", "%Y %b %d %H:%M:%S %z").unwrap();
-            text.0 = (t0 + Duration::seconds((state_keeper.current_step as f64 * state_keeper.dt) as i64)).to_string();
+            let epoch = step_to_epoch(&state_keeper, state_keeper.current_step);
+            text.0 = format!("{}\nJD {:.5} TDB", epoch, epoch.julian_date(TimeScale::Tdb));
+            if let Some((primary, secondary)) = state_keeper.synodic {
+                text.0 += &format!("\n{} (rotating)", frames::Frame::Synodic(primary, secondary).name(&state_keeper));
+            }
         } else if text_overlay.id == 1 {
             let parent_id = state_keeper.info.get(&camera_state.focused).unwrap().kepler_parent;
             let parent_state = state_keeper.state.get(&state_keeper.current_step).unwrap().get(&parent_id).unwrap();
             let state = state_keeper.state.get(&state_keeper.current_step).unwrap().get(&camera_state.focused).unwrap();
-            let current_oe = oe_from_rv(state_keeper.info.get(&parent_id).unwrap().mu, &sub_body_state(state, parent_state));
-            text.0 = current_oe.to_string();
+            let relative_state = camera_state.frame.rotate_from_icrf(&state_keeper, state_keeper.current_step, &sub_body_state(state, parent_state));
+            let current_elements = Elements::from_rv(state_keeper.info.get(&parent_id).unwrap().mu, &relative_state);
+            text.0 = format!("{} (F to change frame)\n{}", camera_state.frame.name(&state_keeper), current_elements);
         } else if text_overlay.id == 2 {
             if let Some(f) = &state_keeper.interplanetary {
-                text.0 = format!("dv1: {}, dv2: {}", f.dv1 as i32, f.dv2 as i32);
+                text.0 = f.to_string();
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A camera 20 km off a moon 1.5 AU from the origin (about Phobos, with Mars at aphelion) has to land within a
+    // meter of where it's aimed once it's gone through the grid
+    #[test]
+    fn camera_placement_near_a_distant_moon_is_sub_meter() {
+        let grid = Grid::<i64>::default();
+        let moon = DVec3::new(1.5 * AU + 1234.5678, 9.3771e6 - 0.25, 2.1e5 + 0.125);
+        let dist = 2.0e4;
+        let placed = |position: DVec3| {
+            let (cell, translation) = grid.translation_to_grid(position);
+            grid.grid_position_double(&cell, &Transform::from_translation(translation))
+        };
+
+        let (position, _) = orbit_placement(DMat3::IDENTITY, 0.0, 0.0, dist, moon);
+        let error = (placed(position) - (moon + DVec3::X * dist)).length();
+        assert!(error < 1.0, "camera placed {} m off", error);
+
+        let axes = DMat3::from_rotation_z(0.3) * DMat3::from_rotation_x(0.4);
+        for (pan, tilt) in [(1.0, 0.5), (-2.5, -1.2), (3.0, 1.5)] {
+            let (position, forward) = orbit_placement(axes, pan, tilt, dist, moon);
+            let offset = placed(position) - moon;
+            assert!((offset.length() - dist).abs() < 1.0, "camera {} m from the moon, not {} m", offset.length(), dist);
+            assert!((offset - forward * dist).length() < 1.0, "camera placed {} m off", (offset - forward * dist).length());
+        }
+    }
+}
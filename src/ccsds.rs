@@ -0,0 +1,620 @@
+use std::fs;
+use std::fmt::Write;
+use bevy::prelude::*;
+use bevy_math::DVec3;
+use big_space::prelude::*;
+use chrono::{NaiveDateTime, Utc};
+use crate::camera::CameraState;
+use crate::frames::Frame;
+use crate::interplanetary::Interplanetary;
+use crate::keplerian::oe_from_rv;
+use crate::lines::{empty_line, LineCache, LineSource, View};
+use crate::time::{epoch_to_step, step_to_epoch, Epoch, TimeScale};
+use crate::{body_shown, BodyState, RootGrid, StateKeeper};
+// CCSDS Orbit Data Messages (CCSDS 502.0-B-3), in their KVN text form, for trading trajectories with GMAT, STK,
+// Orekit and the like: Orbit Ephemeris Messages (OEM) of body and transfer histories out and externally designed
+// trajectories in, and Orbit Parameter Messages (OPM) of transfer designs out. States are written in ICRF relative to
+// a center body, in km and km/s, at TDB epochs. EME2000 and GCRF are read as ICRF, the ~20 mas frame bias between
+// them being far below anything drawn here.
+// https://public.ccsds.org/Pubs/502x0b3e1.pdf
+
+const EPOCH_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3f";
+// States per osculating orbit when writing a body's history, plenty for the 7th degree interpolation readers will do
+const SAMPLES_PER_ORBIT: f64 = 200.0;
+
+// One OEM segment: a stretch of one object's states about one center
+pub struct OemSegment {
+    pub object_name: String,
+    pub object_id: String,
+    pub center_name: String,
+    pub ref_frame: String,
+    pub time_system: TimeScale,
+    pub comments: Vec<String>,
+    pub states: Vec<(Epoch, BodyState)>, // Relative to the center, ICRF axes, meters and m/s
+}
+
+// CCSDS (SPICE) names for the catalog's bodies
+pub fn ccsds_name(name: &str) -> String {
+    match name {
+        "Luna" => "MOON".to_string(),
+        _ => name.to_uppercase(),
+    }
+}
+
+// The body a CCSDS center name refers to, if it's in the simulation
+pub fn center_id(state_keeper: &StateKeeper, center_name: &str) -> Option<u32> {
+    state_keeper.info.iter()
+        .find(|(_, info)| ccsds_name(&info.name) == center_name.to_uppercase())
+        .map(|(id, _)| *id)
+}
+
+fn scale_name(scale: TimeScale) -> &'static str {
+    match scale {
+        TimeScale::Utc => "UTC",
+        TimeScale::Tai => "TAI",
+        TimeScale::Tt => "TT",
+        TimeScale::Tdb => "TDB",
+    }
+}
+
+fn format_epoch(epoch: &Epoch, scale: TimeScale) -> String {
+    epoch.calendar(scale).format(EPOCH_FORMAT).to_string()
+}
+
+// Calendar ("2025-04-01T12:00:00.000") or day of year ("2025-091T12:00:00") epochs, read on the given scale
+fn parse_epoch(text: &str, scale: TimeScale) -> Result<Epoch, String> {
+    let text = text.trim().trim_end_matches('Z');
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%jT%H:%M:%S%.f"))
+        .map(|date| Epoch::from_calendar(scale, date))
+        .map_err(|e| format!("Couldn't read epoch \"{}\": {}", text, e))
+}
+
+fn state_line(epoch: &Epoch, scale: TimeScale, state: &BodyState) -> String {
+    let [r, v] = state.map(|x| x / 1000.0);
+    format!(
+        "{} {:.6} {:.6} {:.6} {:.9} {:.9} {:.9}",
+        format_epoch(epoch, scale), r.x, r.y, r.z, v.x, v.y, v.z
+    )
+}
+
+fn header(kind: &str) -> String {
+    format!(
+        "CCSDS_{}_VERS = 2.0\nCREATION_DATE = {}\nORIGINATOR = {}\n",
+        kind, Utc::now().format("%Y-%m-%dT%H:%M:%S"), env!("CARGO_PKG_NAME").to_uppercase()
+    )
+}
+
+impl OemSegment {
+    // A body's propagated history relative to its parent, every stride steps from first to last
+    pub fn from_body(state_keeper: &StateKeeper, body: u32, first: u32, last: u32, stride: u32) -> Self {
+        let info = state_keeper.info.get(&body).unwrap();
+        let parent = info.kepler_parent;
+        let states = (first..=last.min(state_keeper.last_step_computed)).step_by(stride.max(1) as usize)
+            .map(|step| {
+                let states = state_keeper.state.get(&step).unwrap();
+                let state = states.get(&body).unwrap();
+                let center = states.get(&parent).unwrap();
+                (step_to_epoch(state_keeper, step), [state[0] - center[0], state[1] - center[1]])
+            })
+            .collect();
+        OemSegment {
+            object_name: ccsds_name(&info.name),
+            object_id: ccsds_name(&info.name),
+            center_name: ccsds_name(&state_keeper.info.get(&parent).unwrap().name),
+            ref_frame: "ICRF".to_string(),
+            time_system: TimeScale::Tdb,
+            comments: vec![format!("N-body propagation, {} s steps", state_keeper.dt)],
+            states,
+        }
+    }
+
+    // A stride that gives about SAMPLES_PER_ORBIT states per osculating orbit of the body about its parent
+    pub fn orbit_stride(state_keeper: &StateKeeper, body: u32) -> u32 {
+        let info = state_keeper.info.get(&body).unwrap();
+        let states = state_keeper.state.get(&state_keeper.current_step).unwrap();
+        let state = states.get(&body).unwrap();
+        let center = states.get(&info.kepler_parent).unwrap();
+        let mu = state_keeper.info.get(&info.kepler_parent).unwrap().mu;
+        let a = oe_from_rv(mu, &[state[0] - center[0], state[1] - center[1]]).a;
+        if a <= 0.0 {
+            return 1;
+        }
+        let period = 2.0 * std::f64::consts::PI * (a.powi(3) / mu).sqrt();
+        ((period / SAMPLES_PER_ORBIT / state_keeper.dt) as u32).max(1)
+    }
+
+    // The transfer's Lambert arc about the Sun, departure to arrival
+    pub fn from_transfer(state_keeper: &StateKeeper, ip: &Interplanetary, stride: u32) -> Self {
+        let mu = state_keeper.info.get(&ip.body0).unwrap().mu;
+        let last = ip.arrival_step.min(state_keeper.last_step_computed);
+        let mut steps: Vec<u32> = (ip.departure_step..=last).step_by(stride.max(1) as usize).collect();
+        if steps.last() != Some(&last) {
+            steps.push(last);
+        }
+        let states = steps.into_iter()
+            .filter_map(|step| {
+                let sun = state_keeper.state.get(&step).unwrap().get(&ip.body0).unwrap();
+                ip.transfer_state(mu, state_keeper.dt, step)
+                    .map(|state| (step_to_epoch(state_keeper, step), [state[0] - sun[0], state[1] - sun[1]]))
+            })
+            .collect();
+        let name = |id| ccsds_name(&state_keeper.info.get(&id).unwrap().name);
+        OemSegment {
+            object_name: "TRANSFER".to_string(),
+            object_id: format!("{}-{}", name(ip.body1), name(ip.body2)),
+            center_name: name(ip.body0),
+            ref_frame: "ICRF".to_string(),
+            time_system: TimeScale::Tdb,
+            comments: vec![format!("Lambert arc, {}", if ip.oe0.e < 1.0 { "elliptic" } else { "hyperbolic" })],
+            states,
+        }
+    }
+
+    // Positions relative to the center normally (so they're drawn around where it is at the current step), or traced
+    // out in the rotating frame over the segment's epochs when the synodic display is on
+    pub fn display_points(&self, state_keeper: &StateKeeper, center: u32) -> Vec<DVec3> {
+        match state_keeper.synodic {
+            Some((primary, secondary)) => {
+                let frame = Frame::Synodic(primary, secondary);
+                let last_epoch = step_to_epoch(state_keeper, state_keeper.last_step_computed);
+                self.states.iter()
+                    .filter(|(epoch, _)| *epoch >= state_keeper.epoch && *epoch <= last_epoch)
+                    .map(|(epoch, state)| {
+                        let step = epoch_to_step(state_keeper, epoch);
+                        let c = state_keeper.state.get(&step).unwrap().get(&center).unwrap();
+                        frame.transform_from_icrf(state_keeper, step, &[c[0] + state[0], c[1] + state[1]])[0]
+                    })
+                    .collect()
+            }
+            None => self.states.iter().map(|(_, state)| state[0]).collect(),
+        }
+    }
+}
+
+pub fn write_oem(path: &str, segments: &[OemSegment]) -> Result<(), String> {
+    let mut text = header("OEM");
+    for segment in segments {
+        let (Some(first), Some(last)) = (segment.states.first(), segment.states.last()) else { continue };
+        let scale = segment.time_system;
+        writeln!(text, "\nMETA_START").unwrap();
+        writeln!(text, "OBJECT_NAME = {}", segment.object_name).unwrap();
+        writeln!(text, "OBJECT_ID = {}", segment.object_id).unwrap();
+        writeln!(text, "CENTER_NAME = {}", segment.center_name).unwrap();
+        writeln!(text, "REF_FRAME = {}", segment.ref_frame).unwrap();
+        writeln!(text, "TIME_SYSTEM = {}", scale_name(scale)).unwrap();
+        writeln!(text, "START_TIME = {}", format_epoch(&first.0, scale)).unwrap();
+        writeln!(text, "STOP_TIME = {}", format_epoch(&last.0, scale)).unwrap();
+        writeln!(text, "INTERPOLATION = HERMITE").unwrap();
+        writeln!(text, "INTERPOLATION_DEGREE = 7").unwrap();
+        writeln!(text, "META_STOP\n").unwrap();
+        for comment in &segment.comments {
+            writeln!(text, "COMMENT {}", comment).unwrap();
+        }
+        for (epoch, state) in &segment.states {
+            writeln!(text, "{}", state_line(epoch, scale, state)).unwrap();
+        }
+    }
+    fs::write(path, text).map_err(|e| format!("Couldn't write {}: {}", path, e))
+}
+
+// Every segment of an OEM. Accelerations and covariance are skipped; states must be in an ICRF-aligned frame.
+pub fn read_oem(path: &str) -> Result<Vec<OemSegment>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+    let mut segments = Vec::new();
+    let mut in_meta = false;
+    let mut in_covariance = false;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let error = |message: String| format!("{} line {}: {}", path, number + 1, message);
+        if line.is_empty() {
+            continue;
+        }
+        match line {
+            "META_START" => {
+                in_meta = true;
+                segments.push(OemSegment {
+                    object_name: String::new(),
+                    object_id: String::new(),
+                    center_name: String::new(),
+                    ref_frame: String::new(),
+                    time_system: TimeScale::Utc,
+                    comments: Vec::new(),
+                    states: Vec::new(),
+                });
+                continue;
+            }
+            "META_STOP" => {
+                in_meta = false;
+                continue;
+            }
+            "COVARIANCE_START" => {
+                in_covariance = true;
+                continue;
+            }
+            "COVARIANCE_STOP" => {
+                in_covariance = false;
+                continue;
+            }
+            _ if in_covariance => continue,
+            _ => {}
+        }
+        let Some(segment) = segments.last_mut() else {
+            continue; // Header keywords
+        };
+        if let Some(comment) = line.strip_prefix("COMMENT") {
+            segment.comments.push(comment.trim().to_string());
+        } else if in_meta {
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(error(format!("expected KEY = value, got \"{}\"", line)));
+            };
+            let value = value.trim().to_string();
+            match key.trim() {
+                "OBJECT_NAME" => segment.object_name = value,
+                "OBJECT_ID" => segment.object_id = value,
+                "CENTER_NAME" => segment.center_name = value,
+                "REF_FRAME" => match value.as_str() {
+                    "ICRF" | "EME2000" | "GCRF" => segment.ref_frame = value,
+                    _ => return Err(error(format!("unsupported REF_FRAME {}", value))),
+                },
+                "TIME_SYSTEM" => segment.time_system = match value.as_str() {
+                    "UTC" => TimeScale::Utc,
+                    "TAI" => TimeScale::Tai,
+                    "TT" => TimeScale::Tt,
+                    "TDB" => TimeScale::Tdb,
+                    _ => return Err(error(format!("unsupported TIME_SYSTEM {}", value))),
+                },
+                _ => {}
+            }
+        } else {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 7 {
+                return Err(error(format!("expected an epoch and six state components, got \"{}\"", line)));
+            }
+            let epoch = parse_epoch(fields[0], segment.time_system).map_err(error)?;
+            let mut values = [0.0; 6];
+            for (value, field) in values.iter_mut().zip(&fields[1..7]) {
+                *value = field.parse::<f64>().map_err(|e| error(format!("\"{}\": {}", field, e)))? * 1000.0;
+            }
+            segment.states.push((epoch, [
+                DVec3::new(values[0], values[1], values[2]),
+                DVec3::new(values[3], values[4], values[5]),
+            ]));
+        }
+    }
+    if segments.is_empty() {
+        return Err(format!("{}: no segments", path));
+    }
+    Ok(segments)
+}
+
+// The transfer as an OPM: the spacecraft riding along with the departure body at the departure epoch, an impulse
+// onto the Lambert arc there, and one matching the arrival body's velocity at the end. Those are the heliocentric
+// v_inf vectors; the periapsis burns of the patched conic hyperbolas, which stand in for them, are in the comments.
+// The Keplerian block describes the same pre-burn state as the state vector, as the standard requires, so the
+// transfer arc's elements after the first maneuver only go in comments.
+pub fn write_opm(state_keeper: &StateKeeper, ip: &Interplanetary, path: &str) -> Result<(), String> {
+    let mu = state_keeper.info.get(&ip.body0).unwrap().mu;
+    let at = |step: u32, body: u32| *state_keeper.state.get(&step).unwrap().get(&body).unwrap();
+    let sun = at(ip.departure_step, ip.body0);
+    let departure = at(ip.departure_step, ip.body1);
+    let arrival = at(ip.arrival_step, ip.body2);
+    let start = ip.transfer_state(mu, state_keeper.dt, ip.departure_step).unwrap();
+    let end = ip.transfer_state(mu, state_keeper.dt, ip.arrival_step).unwrap();
+    let state = [departure[0] - sun[0], departure[1] - sun[1]];
+    let oe = oe_from_rv(mu, &state);
+    let transfer_oe = oe_from_rv(mu, &[start[0] - sun[0], start[1] - sun[1]]);
+    let name = |id| ccsds_name(&state_keeper.info.get(&id).unwrap().name);
+    let epoch = |step| format_epoch(&step_to_epoch(state_keeper, step), TimeScale::Tdb);
+
+    let mut text = header("OPM");
+    writeln!(text, "\nOBJECT_NAME = TRANSFER").unwrap();
+    writeln!(text, "OBJECT_ID = {}-{}", name(ip.body1), name(ip.body2)).unwrap();
+    writeln!(text, "CENTER_NAME = {}", name(ip.body0)).unwrap();
+    writeln!(text, "REF_FRAME = ICRF").unwrap();
+    writeln!(text, "TIME_SYSTEM = TDB\n").unwrap();
+    for line in ip.to_string().lines() {
+        writeln!(text, "COMMENT {}", line).unwrap();
+    }
+    writeln!(text, "COMMENT State of {} at departure, before the first maneuver", name(ip.body1)).unwrap();
+    writeln!(text, "EPOCH = {}", epoch(ip.departure_step)).unwrap();
+    for (key, value) in ["X", "Y", "Z"].iter().zip(state[0].to_array()) {
+        writeln!(text, "{} = {:.6} [km]", key, value / 1000.0).unwrap();
+    }
+    for (key, value) in ["X_DOT", "Y_DOT", "Z_DOT"].iter().zip(state[1].to_array()) {
+        writeln!(text, "{} = {:.9} [km/s]", key, value / 1000.0).unwrap();
+    }
+    writeln!(text, "\nCOMMENT Osculating elements of {} at departure", name(ip.body1)).unwrap();
+    writeln!(text, "COMMENT Transfer arc after the first maneuver: a {:.6} km, e {:.9}, i {:.9} deg, RAAN {:.9} deg, AOP {:.9} deg, TA {:.9} deg",
+        transfer_oe.a / 1000.0, transfer_oe.e, transfer_oe.i.to_degrees(), transfer_oe.Ω.to_degrees(), transfer_oe.ω.to_degrees(), transfer_oe.f.to_degrees(),
+    ).unwrap();
+    writeln!(text, "SEMI_MAJOR_AXIS = {:.6} [km]", oe.a / 1000.0).unwrap();
+    writeln!(text, "ECCENTRICITY = {:.9}", oe.e).unwrap();
+    writeln!(text, "INCLINATION = {:.9} [deg]", oe.i.to_degrees()).unwrap();
+    writeln!(text, "RA_OF_ASC_NODE = {:.9} [deg]", oe.Ω.to_degrees()).unwrap();
+    writeln!(text, "ARG_OF_PERICENTER = {:.9} [deg]", oe.ω.to_degrees()).unwrap();
+    writeln!(text, "TRUE_ANOMALY = {:.9} [deg]", oe.f.to_degrees()).unwrap();
+    writeln!(text, "GM = {:.6} [km**3/s**2]", mu / 1e9).unwrap();
+
+    let maneuvers = [
+        (ip.departure_step, start[1] - departure[1], format!("Departure from {}, v_inf {:.1} m/s", name(ip.body1), ip.v_inf1)),
+        (ip.arrival_step, arrival[1] - end[1], format!("Arrival at {}, v_inf {:.1} m/s", name(ip.body2), ip.v_inf2)),
+    ];
+    for (step, dv, comment) in maneuvers {
+        writeln!(text, "\nCOMMENT {}", comment).unwrap();
+        writeln!(text, "MAN_EPOCH_IGNITION = {}", epoch(step)).unwrap();
+        writeln!(text, "MAN_DURATION = 0.0 [s]").unwrap();
+        writeln!(text, "MAN_DELTA_MASS = 0.0 [kg]").unwrap();
+        writeln!(text, "MAN_REF_FRAME = ICRF").unwrap();
+        for (key, value) in ["MAN_DV_1", "MAN_DV_2", "MAN_DV_3"].iter().zip(dv.to_array()) {
+            writeln!(text, "{} = {:.9} [km/s]", key, value / 1000.0).unwrap();
+        }
+    }
+    fs::write(path, text).map_err(|e| format!("Couldn't write {}: {}", path, e))
+}
+
+// The OEM files named in the scenario, with the segments whose center isn't in the simulation left out
+pub fn load_ephemerides(state_keeper: &StateKeeper, paths: &[String]) -> Vec<(u32, OemSegment)> {
+    let mut ephemerides = Vec::new();
+    for path in paths {
+        match read_oem(path) {
+            Ok(segments) => for segment in segments {
+                match center_id(state_keeper, &segment.center_name) {
+                    Some(center) => ephemerides.push((center, segment)),
+                    None => error!("{}: {} is centered on {}, which isn't simulated", path, segment.object_name, segment.center_name),
+                }
+            },
+            Err(e) => error!("{}", e),
+        }
+    }
+    ephemerides
+}
+
+#[derive(Component)]
+pub struct EphemerisDisplay {
+    pub index: usize,
+}
+
+pub fn spawn_ephemeris_displays(
+    mut commands: Commands,
+    state_keeper: Res<StateKeeper>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    root_grid: Single<Entity, With<RootGrid>>,
+) {
+    for index in 0..state_keeper.ephemerides.len() {
+        let line = commands.spawn((
+            Mesh3d(meshes.add(empty_line())),
+            MeshMaterial3d(materials.add(StandardMaterial {
+                base_color: Color::WHITE,
+                emissive: LinearRgba::new(0.2, 0.9, 1.0, 0.8),
+                unlit: true,
+                ..default()
+            })),
+            Transform::default(),
+            GridCell::<i64>::default(),
+            EphemerisDisplay { index },
+            LineCache::default(),
+        )).id();
+        commands.entity(*root_grid).add_child(line);
+    }
+}
+
+// Draw the scenario's loaded ephemerides wherever their center bodies are shown
+pub fn display_ephemerides(
+    state_keeper: Res<StateKeeper>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    root_grid: Single<&Grid<i64>, With<RootGrid>>,
+    mut lines: Query<(&EphemerisDisplay, &Mesh3d, &mut GridCell<i64>, &mut Transform, &mut LineCache)>,
+) {
+    let view = View::of(&state_keeper);
+    for (display, mesh3d, mut grid_cell, mut transform, mut cache) in lines.iter_mut() {
+        let (center, segment) = &state_keeper.ephemerides[display.index];
+        let shown = body_shown(&state_keeper, *center);
+        if cache.stale(view, if shown { LineSource::Fixed } else { LineSource::Empty }) {
+            let points = if shown { segment.display_points(&state_keeper, *center) } else { Vec::new() };
+            cache.rebuild(&mut meshes, &mesh3d.0, &points);
+        }
+        let origin = match state_keeper.synodic {
+            Some(_) => DVec3::ZERO,
+            None => state_keeper.state.get(&state_keeper.current_step).unwrap().get(center).unwrap()[0],
+        };
+        cache.place(&root_grid, origin, &mut grid_cell, &mut transform);
+    }
+}
+
+// Press O to export the focused body's history about its parent as an OEM, and the selected transfer as an OEM of
+// its arc and an OPM of its maneuvers
+pub fn export_ccsds(
+    keys: Res<ButtonInput<KeyCode>>,
+    state_keeper: Res<StateKeeper>,
+    camera_state: Single<&CameraState>,
+) {
+    if !keys.just_pressed(KeyCode::KeyO) {
+        return;
+    }
+    let body = camera_state.focused;
+    let info = state_keeper.info.get(&body).unwrap();
+    if info.kepler_parent != body {
+        let stride = OemSegment::orbit_stride(&state_keeper, body);
+        let segment = OemSegment::from_body(&state_keeper, body, 0, state_keeper.last_step_computed, stride);
+        let path = format!("{}.oem", info.name.to_lowercase());
+        match write_oem(&path, &[segment]) {
+            Ok(()) => info!("Wrote {}", path),
+            Err(e) => error!("{}", e),
+        }
+    }
+    if let Some(ip) = &state_keeper.interplanetary {
+        let stride = ((ip.arrival_step - ip.departure_step) / 1000).max(1);
+        match write_oem("transfer.oem", &[OemSegment::from_transfer(&state_keeper, ip, stride)])
+            .and_then(|_| write_opm(&state_keeper, ip, "transfer.opm")) {
+            Ok(()) => info!("Wrote transfer.oem and transfer.opm"),
+            Err(e) => error!("{}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use bevy_math::DVec3;
+    use crate::interplanetary::interplanetary;
+    use crate::keplerian::{rv_from_oe, OE};
+    use crate::time::{step_to_epoch, Epoch, TimeScale};
+    use crate::StateKeeper;
+    use super::{format_epoch, read_oem, write_oem, write_opm, OemSegment};
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(name).to_str().unwrap().to_string()
+    }
+
+    // Everything written comes back, epochs to the millisecond (truncated) and states to the mm and µm/s written
+    #[test]
+    fn oem_round_trip() {
+        let start = Epoch::parse("2026-03-01 00:00:00 TDB").unwrap();
+        let segment = |center: &str, scale: TimeScale, scale_factor: f64| OemSegment {
+            object_name: "MARS".to_string(),
+            object_id: "499".to_string(),
+            center_name: center.to_string(),
+            ref_frame: "ICRF".to_string(),
+            time_system: scale,
+            comments: vec!["Round trip".to_string()],
+            states: (0..5).map(|n| (
+                start.add_seconds(n as f64 * 3600.25),
+                [
+                    DVec3::new(1.2345678e11, -9.87654321e10, 4.4e10 + n as f64) * scale_factor,
+                    DVec3::new(-12345.678, 23456.789, 1234.5678 + n as f64) * scale_factor,
+                ],
+            )).collect(),
+        };
+        let written = [segment("SUN", TimeScale::Tdb, 1.0), segment("EARTH", TimeScale::Utc, 1e-4)];
+        let path = temp_path("oem_round_trip.oem");
+        write_oem(&path, &written).unwrap();
+        let read = read_oem(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.len(), written.len());
+        for (read, written) in read.iter().zip(&written) {
+            assert_eq!(read.object_name, written.object_name);
+            assert_eq!(read.center_name, written.center_name);
+            assert_eq!(read.ref_frame, written.ref_frame);
+            assert_eq!(read.time_system, written.time_system);
+            assert_eq!(read.comments, written.comments);
+            assert_eq!(read.states.len(), written.states.len());
+            for ((read_epoch, read_state), (written_epoch, written_state)) in read.states.iter().zip(&written.states) {
+                assert!(read_epoch.seconds_since(written_epoch).abs() < 1e-3, "{} vs {}", read_epoch, written_epoch);
+                assert!(read_state[0].abs_diff_eq(written_state[0], 5e-4 + 1e-15 * written_state[0].length()), "{} vs {}", read_state[0], written_state[0]);
+                assert!(read_state[1].abs_diff_eq(written_state[1], 5e-7 + 1e-15 * written_state[1].length()), "{} vs {}", read_state[1], written_state[1]);
+            }
+        }
+    }
+
+    // A hand written KVN file with comments everywhere, day of year epochs, and a covariance block to skip
+    #[test]
+    fn reads_kvn_with_comments() {
+        let text = "\
+CCSDS_OEM_VERS = 2.0
+COMMENT Made by hand
+CREATION_DATE = 2026-01-01T00:00:00
+ORIGINATOR = TEST
+
+META_START
+COMMENT Inside the metadata
+OBJECT_NAME = PROBE
+OBJECT_ID = 2026-001A
+CENTER_NAME = Earth
+REF_FRAME = EME2000
+TIME_SYSTEM = UTC
+START_TIME = 2026-032T00:00:00
+STOP_TIME = 2026-032T00:01:00
+META_STOP
+
+COMMENT Before the data
+2026-032T00:00:00.000 7000.0 0.0 0.0 0.0 7.5 0.0
+2026-02-01T00:01:00 6999.0 450.0 0.0 -0.48 7.49 0.0
+
+COVARIANCE_START
+EPOCH = 2026-032T00:00:00
+COV_REF_FRAME = RTN
+1.0
+COVARIANCE_STOP
+";
+        let path = temp_path("reads_kvn_with_comments.oem");
+        fs::write(&path, text).unwrap();
+        let segments = read_oem(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(segments.len(), 1);
+        let segment = &segments[0];
+        assert_eq!(segment.object_name, "PROBE");
+        assert_eq!(segment.object_id, "2026-001A");
+        assert_eq!(segment.center_name, "Earth");
+        assert_eq!(segment.ref_frame, "EME2000");
+        assert_eq!(segment.time_system, TimeScale::Utc);
+        assert_eq!(segment.comments, ["Inside the metadata", "Before the data"]);
+        assert_eq!(segment.states.len(), 2);
+        assert!((segment.states[1].0.seconds_since(&segment.states[0].0) - 60.0).abs() < 1e-6);
+        assert_eq!(segment.states[0].0, Epoch::parse("2026-02-01 00:00:00 UTC").unwrap());
+        assert!(segment.states[1].1[0].abs_diff_eq(DVec3::new(6999e3, 450e3, 0.0), 1e-9));
+        assert!(segment.states[1].1[1].abs_diff_eq(DVec3::new(-480.0, 7490.0, 0.0), 1e-9));
+    }
+
+    // The OPM's KEY = value lines in order, without units
+    fn opm_values(text: &str) -> Vec<(String, String)> {
+        text.lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim().to_string(), value.split('[').next().unwrap().trim().to_string()))
+            .collect()
+    }
+
+    // A transfer written as an OPM gives back its departure epoch and state, elements describing that same state, and
+    // both maneuvers
+    #[test]
+    fn opm_round_trip() {
+        let mut app = crate::tests::headless_app_with(crate::tests::transfer_scenario());
+        app.update();
+        let state_keeper = app.world().resource::<StateKeeper>();
+        let (departure, arrival) = (state_keeper.interplanetary_selection.0, state_keeper.interplanetary_selection.1);
+        let (departure_step, arrival_step) = crate::tests::TRANSFER_STEPS;
+        let ip = interplanetary(state_keeper, departure_step, arrival_step, departure, arrival, true);
+        let path = temp_path("opm_round_trip.opm");
+        write_opm(state_keeper, &ip, &path).unwrap();
+        let values = opm_values(&fs::read_to_string(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+        let all = |key: &str| values.iter().filter(|(k, _)| k == key).map(|(_, v)| v.clone()).collect::<Vec<String>>();
+        let number = |key: &str| all(key)[0].parse::<f64>().unwrap();
+        let vector = |keys: [&str; 3], index: usize| DVec3::from_array(keys.map(|key| all(key)[index].parse::<f64>().unwrap())) * 1000.0;
+
+        let epoch = |step| format_epoch(&step_to_epoch(state_keeper, step), TimeScale::Tdb);
+        assert_eq!(all("EPOCH"), [epoch(ip.departure_step)]);
+        let at = |step: u32, body: u32| *state_keeper.state.get(&step).unwrap().get(&body).unwrap();
+        let (sun, body) = (at(ip.departure_step, ip.body0), at(ip.departure_step, ip.body1));
+        let state = [body[0] - sun[0], body[1] - sun[1]];
+        let position = vector(["X", "Y", "Z"], 0);
+        let velocity = vector(["X_DOT", "Y_DOT", "Z_DOT"], 0);
+        assert!(position.abs_diff_eq(state[0], 1e-3), "{} vs {}", position, state[0]);
+        assert!(velocity.abs_diff_eq(state[1], 1e-6), "{} vs {}", velocity, state[1]);
+
+        let oe = OE {
+            a: number("SEMI_MAJOR_AXIS") * 1000.0,
+            e: number("ECCENTRICITY"),
+            i: number("INCLINATION").to_radians(),
+            f: number("TRUE_ANOMALY").to_radians(),
+            ω: number("ARG_OF_PERICENTER").to_radians(),
+            Ω: number("RA_OF_ASC_NODE").to_radians(),
+        };
+        let [r, v] = rv_from_oe(number("GM") * 1e9, &oe);
+        assert!(r.abs_diff_eq(state[0], 1e-6 * state[0].length()), "elements put the state at {}, not {}", r, state[0]);
+        assert!(v.abs_diff_eq(state[1], 1e-6 * state[1].length()), "elements put the velocity at {}, not {}", v, state[1]);
+
+        let mu = state_keeper.info.get(&ip.body0).unwrap().mu;
+        let start = ip.transfer_state(mu, state_keeper.dt, ip.departure_step).unwrap();
+        let end = ip.transfer_state(mu, state_keeper.dt, ip.arrival_step).unwrap();
+        assert_eq!(all("MAN_EPOCH_IGNITION"), [epoch(ip.departure_step), epoch(ip.arrival_step)]);
+        let burns = [start[1] - body[1], at(ip.arrival_step, ip.body2)[1] - end[1]];
+        for (index, burn) in burns.iter().enumerate() {
+            let dv = vector(["MAN_DV_1", "MAN_DV_2", "MAN_DV_3"], index);
+            assert!(dv.abs_diff_eq(*burn, 1e-6), "maneuver {}: {} vs {}", index + 1, dv, burn);
+        }
+    }
+}
@@ -0,0 +1,552 @@
+use bevy::prelude::*;
+use bevy::render::mesh::PrimitiveTopology;
+use bevy_math::DVec3;
+use big_space::prelude::*;
+use crate::synodic::{collinear_points, lagrange_points};
+use crate::{BodyInfo, RootGrid, StateKeeper};
+// Circular restricted three-body problem for a pair of bodies. Everything is in the usual normalized units: the pair's
+// separation is 1, their mean motion is 1, and positions are in the rotating frame centered on their barycenter, with
+// the primary at (-mu, 0, 0) and the secondary at (1 - mu, 0, 0). That's the same frame as Frame::Synodic, so results
+// are drawn in the rotating display mode.
+// https://doi.org/10.1007/BF01230232 (Richardson, 1980) for the halo orbit initial guesses
+
+pub type State6 = [f64; 6];
+pub type Mat6 = [[f64; 6]; 6];
+
+const IDENTITY6: Mat6 = {
+    let mut m = [[0.0; 6]; 6];
+    let mut i = 0;
+    while i < 6 {
+        m[i][i] = 1.0;
+        i += 1;
+    }
+    m
+};
+
+// Integration step, in normalized time (about 1/6000th of a revolution of the pair)
+const STEP: f64 = 1e-3;
+
+pub struct Cr3bp {
+    pub mu: f64, // Secondary's share of the pair's mass
+    pub length: f64, // Meters per normalized length unit
+    pub time: f64, // Seconds per normalized time unit
+    pub radii: [f64; 2], // Primary's and secondary's radius in normalized length units
+}
+
+pub struct PeriodicOrbit {
+    pub initial: State6,
+    pub period: f64,
+    pub jacobi: f64,
+    pub states: Vec<State6>, // One period, at every integration step
+    pub stms: Vec<Mat6>, // State transition matrix from initial to each of states
+}
+
+fn mat6_mul(a: &Mat6, b: &Mat6) -> Mat6 {
+    let mut c = [[0.0; 6]; 6];
+    for i in 0..6 {
+        for j in 0..6 {
+            c[i][j] = (0..6).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    c
+}
+
+fn mat6_vec(a: &Mat6, v: &State6) -> State6 {
+    let mut w = [0.0; 6];
+    for i in 0..6 {
+        w[i] = (0..6).map(|k| a[i][k] * v[k]).sum();
+    }
+    w
+}
+
+// Gauss-Jordan elimination with partial pivoting
+fn mat6_inverse(a: &Mat6) -> Mat6 {
+    let mut a = *a;
+    let mut inverse = IDENTITY6;
+    for col in 0..6 {
+        let pivot = (col..6).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs())).unwrap();
+        a.swap(col, pivot);
+        inverse.swap(col, pivot);
+        let scale = a[col][col];
+        for j in 0..6 {
+            a[col][j] /= scale;
+            inverse[col][j] /= scale;
+        }
+        for row in 0..6 {
+            if row != col {
+                let factor = a[row][col];
+                for j in 0..6 {
+                    a[row][j] -= factor * a[col][j];
+                    inverse[row][j] -= factor * inverse[col][j];
+                }
+            }
+        }
+    }
+    inverse
+}
+
+// Eigenvector of the dominant eigenvalue, by power iteration, scaled so its position part has unit length
+fn dominant_eigenvector(a: &Mat6) -> State6 {
+    let mut v = [1.0, 1.0, 1.0, 1.0, 1.0, 1.0];
+    for _ in 0..200 {
+        let w = mat6_vec(a, &v);
+        let norm = w.iter().map(|x| x * x).sum::<f64>().sqrt();
+        v = w.map(|x| x / norm);
+    }
+    normalize_position(v)
+}
+
+fn normalize_position(v: State6) -> State6 {
+    let norm = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    v.map(|x| x / norm)
+}
+
+impl Cr3bp {
+    pub fn new(primary: &BodyInfo, secondary: &BodyInfo, distance: f64) -> Self {
+        Cr3bp {
+            mu: secondary.mu / (primary.mu + secondary.mu),
+            length: distance,
+            time: (distance.powi(3) / (primary.mu + secondary.mu)).sqrt(),
+            radii: [primary.radius / distance, secondary.radius / distance],
+        }
+    }
+
+    // For a pair as they are at a step, with their separation at that step
+    pub fn from_state(state_keeper: &StateKeeper, step: u32, primary: u32, secondary: u32) -> Self {
+        let states = state_keeper.state.get(&step).unwrap();
+        let distance = (states.get(&secondary).unwrap()[0] - states.get(&primary).unwrap()[0]).length();
+        Cr3bp::new(state_keeper.info.get(&primary).unwrap(), state_keeper.info.get(&secondary).unwrap(), distance)
+    }
+
+    // L1 through L5, in normalized units
+    pub fn lagrange_points(&self) -> [DVec3; 5] {
+        lagrange_points(self.mu)
+    }
+
+    pub fn impacts(&self, s: &State6) -> bool {
+        let (r1, r2) = self.distances(s);
+        r1 < self.radii[0] || r2 < self.radii[1]
+    }
+
+    // Distances to the primary and secondary
+    fn distances(&self, s: &State6) -> (f64, f64) {
+        let r1 = ((s[0] + self.mu).powi(2) + s[1] * s[1] + s[2] * s[2]).sqrt();
+        let r2 = ((s[0] - 1.0 + self.mu).powi(2) + s[1] * s[1] + s[2] * s[2]).sqrt();
+        (r1, r2)
+    }
+
+    // Effective (pseudo-)potential, including the centrifugal term
+    pub fn potential(&self, s: &State6) -> f64 {
+        let (r1, r2) = self.distances(s);
+        0.5 * (s[0] * s[0] + s[1] * s[1]) + (1.0 - self.mu) / r1 + self.mu / r2
+    }
+
+    pub fn jacobi(&self, s: &State6) -> f64 {
+        2.0 * self.potential(s) - (s[3] * s[3] + s[4] * s[4] + s[5] * s[5])
+    }
+
+    pub fn derivatives(&self, s: &State6) -> State6 {
+        let mu = self.mu;
+        let (r1, r2) = self.distances(s);
+        let (k1, k2) = ((1.0 - mu) / r1.powi(3), mu / r2.powi(3));
+        [
+            s[3],
+            s[4],
+            s[5],
+            2.0 * s[4] + s[0] - k1 * (s[0] + mu) - k2 * (s[0] - 1.0 + mu),
+            -2.0 * s[3] + s[1] - k1 * s[1] - k2 * s[1],
+            -k1 * s[2] - k2 * s[2],
+        ]
+    }
+
+    // Jacobian of derivatives, which drives the state transition matrix
+    fn jacobian(&self, s: &State6) -> Mat6 {
+        let mu = self.mu;
+        let (r1, r2) = self.distances(s);
+        let (x1, x2, y, z) = (s[0] + mu, s[0] - 1.0 + mu, s[1], s[2]);
+        let (k1, k2) = ((1.0 - mu) / r1.powi(3), mu / r2.powi(3));
+        let (l1, l2) = (3.0 * (1.0 - mu) / r1.powi(5), 3.0 * mu / r2.powi(5));
+
+        let uxx = 1.0 - k1 - k2 + l1 * x1 * x1 + l2 * x2 * x2;
+        let uyy = 1.0 - k1 - k2 + l1 * y * y + l2 * y * y;
+        let uzz = -k1 - k2 + l1 * z * z + l2 * z * z;
+        let uxy = l1 * x1 * y + l2 * x2 * y;
+        let uxz = l1 * x1 * z + l2 * x2 * z;
+        let uyz = l1 * y * z + l2 * y * z;
+
+        [
+            [0.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 0.0, 0.0, 1.0],
+            [uxx, uxy, uxz, 0.0, 2.0, 0.0],
+            [uxy, uyy, uyz, -2.0, 0.0, 0.0],
+            [uxz, uyz, uzz, 0.0, 0.0, 0.0],
+        ]
+    }
+
+    pub fn rk4_step(&self, s: &State6, h: f64) -> State6 {
+        let add = |a: &State6, b: &State6, f: f64| -> State6 { std::array::from_fn(|i| a[i] + b[i] * f) };
+        let k1 = self.derivatives(s);
+        let k2 = self.derivatives(&add(s, &k1, h / 2.0));
+        let k3 = self.derivatives(&add(s, &k2, h / 2.0));
+        let k4 = self.derivatives(&add(s, &k3, h));
+        std::array::from_fn(|i| s[i] + h / 6.0 * (k1[i] + 2.0 * k2[i] + 2.0 * k3[i] + k4[i]))
+    }
+
+    // RK4 step of the state and its state transition matrix together
+    fn rk4_step_stm(&self, s: &State6, stm: &Mat6, h: f64) -> (State6, Mat6) {
+        let derivative = |s: &State6, stm: &Mat6| (self.derivatives(s), mat6_mul(&self.jacobian(s), stm));
+        let add = |s: &State6, stm: &Mat6, ds: &State6, dstm: &Mat6, f: f64| -> (State6, Mat6) {
+            (
+                std::array::from_fn(|i| s[i] + ds[i] * f),
+                std::array::from_fn(|i| std::array::from_fn(|j| stm[i][j] + dstm[i][j] * f)),
+            )
+        };
+        let (k1, m1) = derivative(s, stm);
+        let (s2, stm2) = add(s, stm, &k1, &m1, h / 2.0);
+        let (k2, m2) = derivative(&s2, &stm2);
+        let (s3, stm3) = add(s, stm, &k2, &m2, h / 2.0);
+        let (k3, m3) = derivative(&s3, &stm3);
+        let (s4, stm4) = add(s, stm, &k3, &m3, h);
+        let (k4, m4) = derivative(&s4, &stm4);
+        (
+            std::array::from_fn(|i| s[i] + h / 6.0 * (k1[i] + 2.0 * k2[i] + 2.0 * k3[i] + k4[i])),
+            std::array::from_fn(|i| std::array::from_fn(|j| stm[i][j] + h / 6.0 * (m1[i][j] + 2.0 * m2[i][j] + 2.0 * m3[i][j] + m4[i][j]))),
+        )
+    }
+
+    // States every integration step over duration, which may be negative to go back in time
+    pub fn propagate(&self, s: &State6, duration: f64) -> Vec<State6> {
+        let steps = (duration.abs() / STEP).ceil().max(1.0) as usize;
+        let h = duration / steps as f64;
+        let mut states = vec![*s];
+        for _ in 0..steps {
+            states.push(self.rk4_step(states.last().unwrap(), h));
+        }
+        states
+    }
+
+    // Propagate with the state transition matrix until the first crossing of y = 0 (after leaving it), refined to
+    // land on the crossing. Returns the state, STM and time there.
+    fn propagate_to_crossing(&self, s: &State6) -> Option<(State6, Mat6, f64)> {
+        let (mut state, mut stm, mut t) = (*s, IDENTITY6, 0.0);
+        for _ in 0..(20.0 / STEP) as usize {
+            let (next, next_stm) = self.rk4_step_stm(&state, &stm, STEP);
+            if t > 10.0 * STEP && next[1].signum() != state[1].signum() {
+                // Newton's method on the time past state that lands on y = 0
+                let mut h = STEP * state[1] / (state[1] - next[1]);
+                for _ in 0..5 {
+                    let (s, _) = self.rk4_step_stm(&state, &stm, h);
+                    h -= s[1] / s[4];
+                }
+                let (s, m) = self.rk4_step_stm(&state, &stm, h);
+                return Some((s, m, t + h));
+            }
+            (state, stm, t) = (next, next_stm, t + STEP);
+        }
+        None
+    }
+
+    // Richardson's third order approximation of a halo orbit about L1, L2 or L3 (point 0, 1 or 2) with out of plane
+    // amplitude az, as a state where it crosses the xz plane. With az = 0 this is instead a planar Lyapunov orbit, for
+    // which ax is used as the in plane amplitude. Amplitudes are in normalized length units.
+    pub fn richardson_guess(&self, point: usize, ax: f64, az: f64, northern: bool) -> State6 {
+        let mu = self.mu;
+        let x_l = collinear_points(mu)[point];
+        // Distance to the nearer body: the secondary for L1 and L2, the primary for L3
+        let gamma = if point == 2 { (x_l + mu).abs() } else { (1.0 - mu - x_l).abs() };
+        let c = |n: i32| -> f64 {
+            let sign = if n % 2 == 0 { 1.0 } else { -1.0 };
+            match point {
+                0 => (mu + sign * (1.0 - mu) * gamma.powi(n + 1) / (1.0 - gamma).powi(n + 1)) / gamma.powi(3),
+                1 => (sign * mu + sign * (1.0 - mu) * gamma.powi(n + 1) / (1.0 + gamma).powi(n + 1)) / gamma.powi(3),
+                _ => (1.0 - mu + mu * gamma.powi(n + 1) / (1.0 + gamma).powi(n + 1)) / gamma.powi(3),
+            }
+        };
+        let (c2, c3, c4) = (c(2), c(3), c(4));
+
+        let λ = ((2.0 - c2 + ((c2 - 2.0).powi(2) + 4.0 * (c2 - 1.0) * (1.0 + 2.0 * c2)).sqrt()) / 2.0).sqrt();
+        let k = 2.0 * λ / (λ * λ + 1.0 - c2);
+        let delta = λ * λ - c2;
+        let d1 = 3.0 * λ * λ / k * (k * (6.0 * λ * λ - 1.0) - 2.0 * λ);
+        let d2 = 8.0 * λ * λ / k * (k * (11.0 * λ * λ - 1.0) - 2.0 * λ);
+
+        let a21 = 3.0 * c3 * (k * k - 2.0) / (4.0 * (1.0 + 2.0 * c2));
+        let a22 = 3.0 * c3 / (4.0 * (1.0 + 2.0 * c2));
+        let a23 = -3.0 * c3 * λ / (4.0 * k * d1) * (3.0 * k.powi(3) * λ - 6.0 * k * (k - λ) + 4.0);
+        let a24 = -3.0 * c3 * λ / (4.0 * k * d1) * (2.0 + 3.0 * k * λ);
+        let b21 = -3.0 * c3 * λ / (2.0 * d1) * (3.0 * k * λ - 4.0);
+        let b22 = 3.0 * c3 * λ / d1;
+        let d21 = -c3 / (2.0 * λ * λ);
+
+        let a31 = -9.0 * λ / (4.0 * d2) * (4.0 * c3 * (k * a23 - b21) + k * c4 * (4.0 + k * k))
+            + (9.0 * λ * λ + 1.0 - c2) / (2.0 * d2) * (3.0 * c3 * (2.0 * a23 - k * b21) + c4 * (2.0 + 3.0 * k * k));
+        let a32 = -1.0 / d2 * (9.0 * λ / 4.0 * (4.0 * c3 * (k * a24 - b22) + k * c4)
+            + 1.5 * (9.0 * λ * λ + 1.0 - c2) * (c3 * (k * b22 + d21 - 2.0 * a24) - c4));
+        let b31 = 3.0 / (8.0 * d2) * (8.0 * λ * (3.0 * c3 * (k * b21 - 2.0 * a23) - c4 * (2.0 + 3.0 * k * k))
+            + (9.0 * λ * λ + 1.0 + 2.0 * c2) * (4.0 * c3 * (k * a23 - b21) + k * c4 * (4.0 + k * k)));
+        let b32 = 1.0 / d2 * (9.0 * λ * (c3 * (k * b22 + d21 - 2.0 * a24) - c4)
+            + 3.0 / 8.0 * (9.0 * λ * λ + 1.0 + 2.0 * c2) * (4.0 * c3 * (k * a24 - b22) + k * c4));
+        let d31 = 3.0 / (64.0 * λ * λ) * (4.0 * c3 * a24 + c4);
+        let d32 = 3.0 / (64.0 * λ * λ) * (4.0 * c3 * (a23 - d21) + c4 * (4.0 + k * k));
+
+        let s_denominator = 2.0 * λ * (λ * (1.0 + k * k) - 2.0 * k);
+        let s1 = (1.5 * c3 * (2.0 * a21 * (k * k - 2.0) - a23 * (k * k + 2.0) - 2.0 * k * b21)
+            - 3.0 / 8.0 * c4 * (3.0 * k.powi(4) - 8.0 * k * k + 8.0)) / s_denominator;
+        let s2 = (1.5 * c3 * (2.0 * a22 * (k * k - 2.0) + a24 * (k * k + 2.0) + 2.0 * k * b22 + 5.0 * d21)
+            + 3.0 / 8.0 * c4 * (12.0 - k * k)) / s_denominator;
+        let a_1 = -1.5 * c3 * (2.0 * a21 + a23 + 5.0 * d21) - 3.0 / 8.0 * c4 * (12.0 - k * k);
+        let a_2 = 1.5 * c3 * (a24 - 2.0 * a22) + 9.0 / 8.0 * c4;
+        let l1 = a_1 + 2.0 * λ * λ * s1;
+        let l2 = a_2 + 2.0 * λ * λ * s2;
+
+        // Amplitudes in Richardson's units, where the distance from the nearer body to the libration point is 1
+        let az = az / gamma;
+        let ax = if az > 0.0 { (-(l2 * az * az + delta) / l1).sqrt() } else { ax / gamma };
+        let ω = 1.0 + s1 * ax * ax + s2 * az * az;
+        let δ = if northern { 1.0 } else { -1.0 };
+
+        // At τ1 = 0, where the orbit crosses the xz plane
+        let x = a21 * ax * ax + a22 * az * az - ax + (a23 * ax * ax - a24 * az * az) + (a31 * ax.powi(3) - a32 * ax * az * az);
+        let z = δ * az + δ * d21 * ax * az * (1.0 - 3.0) + δ * (d32 * az * ax * ax - d31 * az.powi(3));
+        let vy = λ * ω * (k * ax + 2.0 * (b21 * ax * ax - b22 * az * az) + 3.0 * (b31 * ax.powi(3) - b32 * ax * az * az));
+
+        // Back to the synodic frame, centered on the barycenter with the pair's separation as the unit
+        [x_l + gamma * x, 0.0, gamma * z, 0.0, gamma * vy, 0.0]
+    }
+
+    // Differentially correct a symmetric periodic orbit crossing the xz plane perpendicularly at s, keeping z fixed
+    // (or x, for planar orbits) and adjusting the other and ẏ until the next crossing is perpendicular too
+    pub fn correct_periodic(&self, s: &State6) -> Option<PeriodicOrbit> {
+        let mut s = *s;
+        let planar = s[2] == 0.0;
+        for _ in 0..50 {
+            let (crossing, stm, half_period) = self.propagate_to_crossing(&s)?;
+            let d = self.derivatives(&crossing);
+            if crossing[3].abs() < 1e-11 && crossing[5].abs() < 1e-11 {
+                return Some(self.periodic_orbit(&s, 2.0 * half_period));
+            }
+            if planar {
+                // δẋ = (Φ45 - ẍ Φ25 / ẏ) δẏ0
+                let dvy = -crossing[3] / (stm[3][4] - d[3] * stm[1][4] / crossing[4]);
+                s[4] += dvy;
+            } else {
+                // [δẋ, δż] in terms of [δx0, δẏ0], holding the crossing on y = 0
+                let m = [
+                    [stm[3][0] - d[3] * stm[1][0] / crossing[4], stm[3][4] - d[3] * stm[1][4] / crossing[4]],
+                    [stm[5][0] - d[5] * stm[1][0] / crossing[4], stm[5][4] - d[5] * stm[1][4] / crossing[4]],
+                ];
+                let det = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+                s[0] += (-crossing[3] * m[1][1] + crossing[5] * m[0][1]) / det;
+                s[4] += (-crossing[5] * m[0][0] + crossing[3] * m[1][0]) / det;
+            }
+            if !s.iter().all(|x| x.is_finite()) {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn periodic_orbit(&self, initial: &State6, period: f64) -> PeriodicOrbit {
+        let steps = (period / STEP).ceil() as usize;
+        let h = period / steps as f64;
+        let mut states = vec![*initial];
+        let mut stms = vec![IDENTITY6];
+        for _ in 0..steps {
+            let (s, stm) = self.rk4_step_stm(states.last().unwrap(), stms.last().unwrap(), h);
+            states.push(s);
+            stms.push(stm);
+        }
+        PeriodicOrbit { initial: *initial, period, jacobi: self.jacobi(initial), states, stms }
+    }
+
+    // Planar Lyapunov orbit about L1, L2 or L3 (point 0, 1 or 2) with roughly the given x amplitude
+    pub fn lyapunov(&self, point: usize, ax: f64) -> Option<PeriodicOrbit> {
+        self.correct_periodic(&self.richardson_guess(point, ax, 0.0, true))
+    }
+
+    // Halo orbit about L1, L2 or L3 (point 0, 1 or 2) with roughly the given z amplitude
+    pub fn halo(&self, point: usize, az: f64, northern: bool) -> Option<PeriodicOrbit> {
+        self.correct_periodic(&self.richardson_guess(point, 0.0, az, northern))
+    }
+
+    // Branches of the stable or unstable manifold of a periodic orbit, seeded at count points evenly spaced around it.
+    // Each seed is displaced by ±epsilon along the (un)stable eigenvector of the monodromy matrix, carried along the
+    // orbit by the state transition matrix, and propagated for duration: forward for the unstable manifold and
+    // backward for the stable one. Branches end where they hit either body.
+    pub fn manifolds(&self, orbit: &PeriodicOrbit, stable: bool, count: usize, epsilon: f64, duration: f64) -> Vec<Vec<State6>> {
+        let monodromy = orbit.stms.last().unwrap();
+        let eigenvector = if stable {
+            dominant_eigenvector(&mat6_inverse(monodromy))
+        } else {
+            dominant_eigenvector(monodromy)
+        };
+        let direction = if stable { -1.0 } else { 1.0 };
+
+        let mut branches = Vec::new();
+        for n in 0..count {
+            let index = n * (orbit.states.len() - 1) / count;
+            let v = normalize_position(mat6_vec(&orbit.stms[index], &eigenvector));
+            for side in [1.0, -1.0] {
+                let seed: State6 = std::array::from_fn(|i| orbit.states[index][i] + side * epsilon * v[i]);
+                let mut branch = self.propagate(&seed, direction * duration);
+                if let Some(impact) = branch.iter().position(|s| self.impacts(s)) {
+                    branch.truncate(impact + 1);
+                }
+                branches.push(branch);
+            }
+        }
+        branches
+    }
+
+    pub fn to_meters(&self, s: &State6) -> DVec3 {
+        DVec3::new(s[0], s[1], s[2]) * self.length
+    }
+}
+
+#[derive(Component)]
+pub struct Cr3bpDisplay {}
+
+// What the H key cycles through, in the current synodic pair
+const ORBIT_CYCLE: [&str; 5] = ["none", "L1 Lyapunov", "L1 halo", "L2 Lyapunov", "L2 halo"];
+
+// Press H, with the rotating display on, to cycle through Lyapunov and halo orbits about the pair's L1 and L2, drawn
+// with their unstable (red) and stable (green) manifolds. Leaving the rotating display clears them.
+pub fn cycle_cr3bp_orbits(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    state_keeper: Res<StateKeeper>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    root_grid: Single<(Entity, &Grid<i64>), With<RootGrid>>,
+    displayed: Query<Entity, With<Cr3bpDisplay>>,
+    mut selection: Local<(usize, Option<(u32, u32)>)>,
+) {
+    let (root_entity, root_grid) = root_grid.into_inner();
+    let pressed = keys.just_pressed(KeyCode::KeyH) && state_keeper.synodic.is_some();
+    if !pressed && selection.1 == state_keeper.synodic {
+        return;
+    }
+
+    for entity in displayed.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    *selection = if pressed && selection.1 == state_keeper.synodic {
+        ((selection.0 + 1) % ORBIT_CYCLE.len(), state_keeper.synodic)
+    } else if pressed {
+        (1, state_keeper.synodic)
+    } else {
+        (0, state_keeper.synodic)
+    };
+    let Some((primary, secondary)) = state_keeper.synodic else { return };
+    if selection.0 == 0 {
+        return;
+    }
+
+    let system = Cr3bp::from_state(&state_keeper, state_keeper.current_step, primary, secondary);
+    let point = (selection.0 - 1) / 2;
+    let gamma = (1.0 - system.mu - collinear_points(system.mu)[point]).abs();
+    let orbit = if selection.0 % 2 == 1 {
+        system.lyapunov(point, 0.1 * gamma)
+    } else {
+        system.halo(point, 0.2 * gamma, true)
+    };
+    let Some(orbit) = orbit else {
+        warn!("{} didn't converge", ORBIT_CYCLE[selection.0]);
+        return;
+    };
+    info!(
+        "{}: period {:.3} days, Jacobi constant {:.6}",
+        ORBIT_CYCLE[selection.0], orbit.period * system.time / 86400.0, orbit.jacobi
+    );
+
+    let mut spawn_line = |states: &[State6], color: LinearRgba| {
+        let points: Vec<DVec3> = states.iter().map(|s| system.to_meters(s)).collect();
+        let p0 = points[0];
+        let (grid_cell, translation) = root_grid.translation_to_grid(p0);
+        let mut mesh = Mesh::new(PrimitiveTopology::LineStrip, Default::default());
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, points.iter().map(|p| (*p - p0).as_vec3()).collect::<Vec<Vec3>>());
+        let line = commands.spawn((
+            Mesh3d(meshes.add(mesh)),
+            MeshMaterial3d(materials.add(StandardMaterial {
+                base_color: Color::WHITE,
+                emissive: color,
+                unlit: true,
+                ..default()
+            })),
+            Transform::from_translation(translation),
+            grid_cell,
+            Cr3bpDisplay {},
+        )).id();
+        commands.entity(root_entity).add_child(line);
+    };
+
+    spawn_line(&orbit.states, LinearRgba::new(1.0, 0.8, 0.2, 0.8));
+    for branch in system.manifolds(&orbit, false, 20, 1e-4, 4.0) {
+        spawn_line(&branch, LinearRgba::new(0.8, 0.1, 0.1, 0.4));
+    }
+    for branch in system.manifolds(&orbit, true, 20, 1e-4, 4.0) {
+        spawn_line(&branch, LinearRgba::new(0.1, 0.8, 0.1, 0.4));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cr3bp, PeriodicOrbit};
+    use crate::synodic::collinear_points;
+
+    // Earth and Moon, from their GM and mean separation
+    fn earth_moon() -> Cr3bp {
+        let distance = 384400e3;
+        let gm = 3.986004418e14 + 4.9048695e12;
+        Cr3bp { mu: 4.9048695e12 / gm, length: distance, time: (distance.powi(3) / gm).sqrt(), radii: [6378.137e3 / distance, 1737.4e3 / distance] }
+    }
+
+    // One period of the orbit gets back to where it started, on a constant Jacobi value
+    fn assert_periodic(system: &Cr3bp, orbit: &PeriodicOrbit, what: &str) {
+        let end = orbit.states.last().unwrap();
+        assert!((0..6).all(|i| (end[i] - orbit.initial[i]).abs() < 1e-6), "{}: {:?} vs {:?}", what, end, orbit.initial);
+        for s in &orbit.states {
+            assert!((system.jacobi(s) - orbit.jacobi).abs() < 1e-9, "{}: Jacobi drifts to {} from {}", what, system.jacobi(s), orbit.jacobi);
+        }
+    }
+
+    // The orbits the H key cycles through, about Earth-Moon L1 and L2
+    #[test]
+    fn l1_l2_orbits_converge() {
+        let system = earth_moon();
+        for point in [0, 1] {
+            let gamma = (1.0 - system.mu - collinear_points(system.mu)[point]).abs();
+            let lyapunov = system.lyapunov(point, 0.1 * gamma).unwrap();
+            assert_periodic(&system, &lyapunov, &format!("L{} Lyapunov", point + 1));
+            assert!(lyapunov.states.iter().all(|s| s[2] == 0.0));
+            let halo = system.halo(point, 0.2 * gamma, true).unwrap();
+            assert_periodic(&system, &halo, &format!("L{} halo", point + 1));
+            assert!(halo.initial[2] > 0.0, "northern halo starts at z = {}", halo.initial[2]);
+            for orbit in [&lyapunov, &halo] {
+                let days = orbit.period * system.time / 86400.0;
+                assert!((10.0..16.0).contains(&days), "L{} period {} days", point + 1, days);
+            }
+        }
+    }
+
+    // A small L3 Lyapunov guess should already cross the x axis nearly perpendicularly, half a period (about π) later
+    #[test]
+    fn l3_lyapunov_guess_is_nearly_periodic() {
+        let system = earth_moon();
+        let guess = system.richardson_guess(2, 0.01, 0.0, true);
+        let (crossing, _, half_period) = system.propagate_to_crossing(&guess).unwrap();
+        assert!(crossing[3].abs() < 1e-4, "ẋ = {}", crossing[3]);
+        assert!((half_period - std::f64::consts::PI).abs() < 0.1, "{}", half_period);
+    }
+
+    #[test]
+    fn l3_lyapunov_converges_about_l3() {
+        let system = earth_moon();
+        let orbit = system.lyapunov(2, 0.01).unwrap();
+        let x_l3 = collinear_points(system.mu)[2];
+        assert!((orbit.initial[0] - x_l3).abs() < 0.02, "{} vs {}", orbit.initial[0], x_l3);
+        let end = orbit.states.last().unwrap();
+        assert!((0..6).all(|i| (end[i] - orbit.initial[i]).abs() < 1e-6), "{:?} vs {:?}", end, orbit.initial);
+    }
+}
@@ -0,0 +1,263 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use bevy::prelude::*;
+use plotters::prelude::*;
+use crate::camera::CameraState;
+use crate::keplerian::{oe_from_rv, OE};
+use crate::time::step_to_epoch;
+use crate::{sub_body_state, StateKeeper};
+// Osculating element time series for a body relative to its kepler_parent, pulled from the propagated states. Averaging
+// them over an orbit gives mean elements, and a linear fit of those gives the secular drift (nodal and apsidal
+// precession, decay of a, etc).
+
+pub struct ElementHistory {
+    pub steps: Vec<u32>,
+    pub times: Vec<f64>, // Seconds since step 0
+    pub osculating: Vec<OE>, // Ω, ω and f are unwrapped, so they're continuous rather than kept in [0, 2π)
+}
+
+// Linear rates, per second, from a least squares fit
+pub struct SecularRates {
+    pub da: f64,
+    pub de: f64,
+    pub di: f64,
+    pub dΩ: f64,
+    pub dω: f64,
+}
+
+impl fmt::Display for SecularRates {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let day = 86400.0;
+        write!(
+            f,
+            "da/dt: {:.6} m/day, de/dt: {:.3e} /day, di/dt: {:.6} deg/day, dΩ/dt: {:.6} deg/day, dω/dt: {:.6} deg/day",
+            self.da * day, self.de * day, (self.di * day).to_degrees(), (self.dΩ * day).to_degrees(), (self.dω * day).to_degrees()
+        )
+    }
+}
+
+// Shift an angle by whole turns so it's as close as possible to the previous sample
+fn unwrap_angle(previous: f64, angle: f64) -> f64 {
+    angle + ((previous - angle) / (2.0 * PI)).round() * 2.0 * PI
+}
+
+// Slope of the least squares line through (x, y)
+fn fit_slope(x: &[f64], y: &[f64]) -> f64 {
+    let n = x.len() as f64;
+    let x_mean = x.iter().sum::<f64>() / n;
+    let y_mean = y.iter().sum::<f64>() / n;
+    let sxy: f64 = x.iter().zip(y).map(|(x, y)| (x - x_mean) * (y - y_mean)).sum();
+    let sxx: f64 = x.iter().map(|x| (x - x_mean).powi(2)).sum();
+    if sxx > 0.0 { sxy / sxx } else { 0.0 }
+}
+
+impl ElementHistory {
+    // Sample every stride steps from start_step up to (not including) end_step, or the last propagated step
+    pub fn from_state(state_keeper: &StateKeeper, body: u32, start_step: u32, end_step: u32, stride: u32) -> Self {
+        let parent = state_keeper.info.get(&body).unwrap().kepler_parent;
+        let mu = state_keeper.info.get(&parent).unwrap().mu;
+        let end_step = end_step.min(state_keeper.last_step_computed + 1);
+
+        let mut history = ElementHistory { steps: Vec::new(), times: Vec::new(), osculating: Vec::new() };
+        for step in (start_step..end_step).step_by(stride.max(1) as usize) {
+            let states = state_keeper.state.get(&step).unwrap();
+            let mut oe = oe_from_rv(mu, &sub_body_state(states.get(&body).unwrap(), states.get(&parent).unwrap()));
+            if let Some(previous) = history.osculating.last() {
+                oe.Ω = unwrap_angle(previous.Ω, oe.Ω);
+                oe.ω = unwrap_angle(previous.ω, oe.ω);
+                oe.f = unwrap_angle(previous.f, oe.f);
+            }
+            history.steps.push(step);
+            history.times.push(step as f64 * state_keeper.dt);
+            history.osculating.push(oe);
+        }
+        history
+    }
+
+    // Number of samples spanning one orbit at the start of the history, the natural averaging window
+    pub fn samples_per_orbit(&self, mu: f64) -> usize {
+        if self.times.len() < 2 || self.osculating[0].a <= 0.0 {
+            return 1;
+        }
+        let period = 2.0 * PI * (self.osculating[0].a.powi(3) / mu).sqrt();
+        ((period / (self.times[1] - self.times[0])).round() as usize).max(1)
+    }
+
+    // Centered moving average over window samples, which removes the short period terms that have the window's
+    // period. Windows are shortened at the ends of the history.
+    pub fn mean_elements(&self, window: usize) -> Vec<OE> {
+        let n = self.osculating.len();
+        let half = window / 2;
+        // Prefix sums of a, e, i, ω and Ω, so each window's average is a single difference
+        let mut sums = vec![[0.0; 5]; n + 1];
+        for (k, oe) in self.osculating.iter().enumerate() {
+            for (j, value) in [oe.a, oe.e, oe.i, oe.ω, oe.Ω].into_iter().enumerate() {
+                sums[k + 1][j] = sums[k][j] + value;
+            }
+        }
+        (0..n).map(|k| {
+            let lo = k.saturating_sub(half);
+            let hi = (k + half + 1).min(n);
+            let average = |j: usize| (sums[hi][j] - sums[lo][j]) / (hi - lo) as f64;
+            OE {
+                a: average(0),
+                e: average(1),
+                i: average(2),
+                f: self.osculating[k].f,
+                ω: average(3),
+                Ω: average(4),
+            }
+        }).collect()
+    }
+
+    pub fn secular_rates(&self, elements: &[OE]) -> SecularRates {
+        let fit = |element: fn(&OE) -> f64| fit_slope(&self.times, &elements.iter().map(element).collect::<Vec<f64>>());
+        SecularRates {
+            da: fit(|oe| oe.a),
+            de: fit(|oe| oe.e),
+            di: fit(|oe| oe.i),
+            dΩ: fit(|oe| oe.Ω),
+            dω: fit(|oe| oe.ω),
+        }
+    }
+
+    pub fn write_csv(&self, state_keeper: &StateKeeper, path: &str, mean: &[OE]) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "step,utc,time_s,a_m,e,i_deg,raan_deg,arg_per_deg,true_anom_deg,mean_a_m,mean_e,mean_i_deg,mean_raan_deg,mean_arg_per_deg")?;
+        for (k, oe) in self.osculating.iter().enumerate() {
+            let m = &mean[k];
+            writeln!(
+                file,
+                "{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                self.steps[k], step_to_epoch(state_keeper, self.steps[k]).to_utc().format("%Y-%m-%dT%H:%M:%S%.3fZ"), self.times[k],
+                oe.a, oe.e, oe.i.to_degrees(), oe.Ω.to_degrees(), oe.ω.to_degrees(), oe.f.to_degrees(),
+                m.a, m.e, m.i.to_degrees(), m.Ω.to_degrees(), m.ω.to_degrees()
+            )?;
+        }
+        Ok(())
+    }
+
+    // One panel per element (a, e, i, Ω, ω) against days since step 0, osculating in grey and mean in blue, with the
+    // secular fit through the mean drawn in red
+    pub fn plot(&self, path: &str, name: &str, mean: &[OE], rates: &SecularRates) -> Result<(), Box<dyn std::error::Error>> {
+        let root = BitMapBackend::new(path, (1200, 1500)).into_drawing_area();
+        root.fill(&WHITE)?;
+        let root = root.titled(&format!("{} elements", name), ("sans-serif", 24))?;
+        let panels = root.split_evenly((5, 1));
+
+        let days: Vec<f64> = self.times.iter().map(|t| t / 86400.0).collect();
+        let elements: [(&str, fn(&OE) -> f64, f64); 5] = [
+            ("a (km)", |oe| oe.a / 1000.0, rates.da / 1000.0),
+            ("e", |oe| oe.e, rates.de),
+            ("i (deg)", |oe| oe.i.to_degrees(), rates.di.to_degrees()),
+            ("Ω (deg)", |oe| oe.Ω.to_degrees(), rates.dΩ.to_degrees()),
+            ("ω (deg)", |oe| oe.ω.to_degrees(), rates.dω.to_degrees()),
+        ];
+
+        for (panel, (label, element, rate)) in panels.iter().zip(elements) {
+            let osculating: Vec<f64> = self.osculating.iter().map(element).collect();
+            let averaged: Vec<f64> = mean.iter().map(element).collect();
+            let (lo, hi) = osculating.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &y| (lo.min(y), hi.max(y)));
+            let pad = ((hi - lo) * 0.05).max(1e-12);
+
+            let mut chart = ChartBuilder::on(panel)
+                .margin(10)
+                .x_label_area_size(30)
+                .y_label_area_size(80)
+                .build_cartesian_2d(days[0]..*days.last().unwrap(), (lo - pad)..(hi + pad))?;
+            chart.configure_mesh().x_desc("Days from t=0").y_desc(label).draw()?;
+
+            chart.draw_series(LineSeries::new(days.iter().copied().zip(osculating), &RGBColor(180, 180, 180)))?;
+            chart.draw_series(LineSeries::new(days.iter().copied().zip(averaged.iter().copied()), &BLUE))?;
+
+            // Fit line through the mean's centroid, with the fitted slope converted to per day
+            let t_mean = days.iter().sum::<f64>() / days.len() as f64;
+            let y_mean = averaged.iter().sum::<f64>() / averaged.len() as f64;
+            let fit = |t: f64| y_mean + rate * 86400.0 * (t - t_mean);
+            chart.draw_series(LineSeries::new([days[0], *days.last().unwrap()].map(|t| (t, fit(t))), &RED))?;
+        }
+
+        root.present()?;
+        Ok(())
+    }
+}
+
+// Press E to export the element history of the focused body over the whole propagation: a CSV of osculating and mean
+// elements, a plot, and the fitted secular rates in the log
+pub fn export_focused_elements(
+    keys: Res<ButtonInput<KeyCode>>,
+    state_keeper: Res<StateKeeper>,
+    camera_state: Single<&CameraState>,
+) {
+    if !keys.just_pressed(KeyCode::KeyE) {
+        return;
+    }
+    let body = camera_state.focused;
+    let info = state_keeper.info.get(&body).unwrap();
+    if info.kepler_parent == body {
+        return;
+    }
+    let mu = state_keeper.info.get(&info.kepler_parent).unwrap().mu;
+
+    // Sample often enough to resolve even Phobos' ~7.6 hour orbit
+    let history = ElementHistory::from_state(&state_keeper, body, 0, state_keeper.step_limit, 10);
+    let mean = history.mean_elements(history.samples_per_orbit(mu));
+    let rates = history.secular_rates(&mean);
+
+    let name = info.name.to_lowercase();
+    if let Err(e) = history.write_csv(&state_keeper, &format!("{}_elements.csv", name), &mean) {
+        error!("Couldn't write {} element history: {}", info.name, e);
+    }
+    if let Err(e) = history.plot(&format!("{}_elements.png", name), &info.name, &mean, &rates) {
+        error!("Couldn't plot {} element history: {}", info.name, e);
+    }
+    info!("{} secular rates: {}", info.name, rates);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use crate::keplerian::OE;
+    use super::ElementHistory;
+
+    // Linear drifts of a, ω and Ω under a short period wobble of one orbit (51 samples), as J2 gives a low orbit
+    #[test]
+    fn secular_rates_of_linear_drifts() {
+        let (a_rate, apsis_rate, node_rate) = (-0.01, 1e-6, -2e-6);
+        let (period, samples) = (5100.0, 51);
+        let steps: Vec<u32> = (0..samples * 100).collect();
+        let times: Vec<f64> = steps.iter().map(|&step| step as f64 * period / samples as f64).collect();
+        let osculating = times.iter().map(|&t| {
+            let wobble = (2.0 * PI * t / period).sin();
+            OE {
+                a: 7e6 + a_rate * t + 2000.0 * wobble,
+                e: 0.001 + 1e-4 * wobble,
+                i: 0.9 + 1e-4 * wobble,
+                f: 2.0 * PI * t / period,
+                ω: 1.0 + apsis_rate * t + 1e-3 * wobble,
+                Ω: 2.0 + node_rate * t + 1e-3 * wobble,
+            }
+        }).collect();
+        let history = ElementHistory { steps, times, osculating };
+        let mu = 4.0 * PI * PI * 7e6f64.powi(3) / period.powi(2);
+        assert_eq!(history.samples_per_orbit(mu), samples as usize);
+
+        // Away from the shortened windows at the ends, a whole orbit's average leaves only the drift
+        let mean = history.mean_elements(history.samples_per_orbit(mu));
+        for k in samples as usize..history.times.len() - samples as usize {
+            let t = history.times[k];
+            assert!((mean[k].a - (7e6 + a_rate * t)).abs() < 1e-3, "{} at {} s", mean[k].a, t);
+            assert!((mean[k].ω - (1.0 + apsis_rate * t)).abs() < 1e-9, "{} at {} s", mean[k].ω, t);
+            assert!((mean[k].Ω - (2.0 + node_rate * t)).abs() < 1e-9, "{} at {} s", mean[k].Ω, t);
+        }
+
+        // The shortened windows at the ends keep some of the wobble, which is large next to a's drift
+        let rates = history.secular_rates(&mean);
+        assert!((rates.da - a_rate).abs() < 1e-2 * a_rate.abs(), "{}", rates.da);
+        assert!((rates.dω - apsis_rate).abs() < 1e-3 * apsis_rate.abs(), "{}", rates.dω);
+        assert!((rates.dΩ - node_rate).abs() < 1e-3 * node_rate.abs(), "{}", rates.dΩ);
+        assert!(rates.de.abs() < 1e-10 && rates.di.abs() < 1e-10, "{} {}", rates.de, rates.di);
+    }
+}
@@ -0,0 +1,259 @@
+use std::f64::consts::PI;
+use std::fmt;
+use bevy_math::DVec3;
+use crate::keplerian::{eccentric_anomaly_from_true_anomaly, oe_from_rv, true_anomaly_from_eccentric_anomaly, OE};
+use crate::BodyState;
+// Equinoctial element sets, which stay well defined for circular and equatorial orbits where Ω, ω and f don't.
+// Both use the prograde formulation, so they're only singular for orbits at exactly i = 180 deg.
+// https://doi.org/10.1007/BF01231018 (Walker, Ireland & Owens, 1985)
+
+// Modified equinoctial elements. Valid for every conic.
+#[derive(Clone)]
+pub struct ModifiedEquinoctial {
+    pub p: f64, // Semi-latus rectum
+    pub f: f64, // e cos(ω + Ω)
+    pub g: f64, // e sin(ω + Ω)
+    pub h: f64, // tan(i/2) cos(Ω)
+    pub k: f64, // tan(i/2) sin(Ω)
+    pub L: f64, // True longitude, Ω + ω + f
+}
+
+// Classical equinoctial elements (Broucke & Cefola). Elliptic orbits only, since they use a and the mean longitude.
+#[derive(Clone)]
+pub struct Equinoctial {
+    pub a: f64,
+    pub h: f64, // e sin(ω + Ω)
+    pub k: f64, // e cos(ω + Ω)
+    pub p: f64, // tan(i/2) sin(Ω)
+    pub q: f64, // tan(i/2) cos(Ω)
+    pub λ: f64, // Mean longitude, Ω + ω + M
+}
+
+// Unit vectors of the equinoctial frame, in the orbit plane, given tan(i/2) cos(Ω) and tan(i/2) sin(Ω)
+fn equinoctial_frame(h: f64, k: f64) -> (DVec3, DVec3) {
+    let s2 = 1.0 + h * h + k * k;
+    let α2 = h * h - k * k;
+    (
+        DVec3::new(1.0 + α2, 2.0 * h * k, -2.0 * k) / s2,
+        DVec3::new(2.0 * h * k, 1.0 - α2, 2.0 * h) / s2,
+    )
+}
+
+impl ModifiedEquinoctial {
+    pub fn from_rv(mu: f64, body_state: &BodyState) -> Self {
+        let [r, v] = *body_state;
+        let h_vec = r.cross(v);
+        let h_hat = h_vec.normalize();
+        let p = h_vec.length_squared() / mu;
+        let h = -h_hat.y / (1.0 + h_hat.z);
+        let k = h_hat.x / (1.0 + h_hat.z);
+
+        let (f_hat, g_hat) = equinoctial_frame(h, k);
+        let e_vec = v.cross(h_vec) / mu - r.normalize();
+        let L = r.dot(g_hat).atan2(r.dot(f_hat)).rem_euclid(2.0 * PI);
+
+        ModifiedEquinoctial { p, f: e_vec.dot(f_hat), g: e_vec.dot(g_hat), h, k, L }
+    }
+
+    #[cfg(test)]
+    pub fn to_rv(&self, mu: f64) -> BodyState {
+        let (f_hat, g_hat) = equinoctial_frame(self.h, self.k);
+        let (sin_L, cos_L) = self.L.sin_cos();
+        let r = self.p / (1.0 + self.f * cos_L + self.g * sin_L);
+        [
+            r * (cos_L * f_hat + sin_L * g_hat),
+            (mu / self.p).sqrt() * (-(sin_L + self.g) * f_hat + (cos_L + self.f) * g_hat),
+        ]
+    }
+
+    pub fn e(&self) -> f64 {
+        (self.f * self.f + self.g * self.g).sqrt()
+    }
+
+    pub fn i(&self) -> f64 {
+        2.0 * (self.h * self.h + self.k * self.k).sqrt().atan()
+    }
+}
+
+impl Equinoctial {
+    pub fn from_modified(mee: &ModifiedEquinoctial) -> Self {
+        let e2 = mee.f * mee.f + mee.g * mee.g;
+        // Eccentric longitude F = E + long_per, from the true anomaly measured off the longitude of periapsis. For a circular
+        // orbit long_per is arbitrary, but then E = f so F = L regardless.
+        let long_per = mee.g.atan2(mee.f);
+        let F = eccentric_anomaly_from_true_anomaly(e2.sqrt(), mee.L - long_per) + long_per;
+        Equinoctial {
+            a: mee.p / (1.0 - e2),
+            h: mee.g,
+            k: mee.f,
+            p: mee.k,
+            q: mee.h,
+            λ: (F - mee.f * F.sin() + mee.g * F.cos()).rem_euclid(2.0 * PI),
+        }
+    }
+
+    pub fn to_modified(&self) -> ModifiedEquinoctial {
+        let e2 = self.h * self.h + self.k * self.k;
+        // Kepler's equation in equinoctial form, λ = F - k sin(F) + h cos(F)
+        let mut F = self.λ;
+        for _ in 0..50 {
+            let step = (F - self.k * F.sin() + self.h * F.cos() - self.λ) / (1.0 - self.k * F.cos() - self.h * F.sin());
+            F -= step;
+            if step.abs() < 1e-15 {
+                break;
+            }
+        }
+        let long_per = self.h.atan2(self.k);
+        ModifiedEquinoctial {
+            p: self.a * (1.0 - e2),
+            f: self.k,
+            g: self.h,
+            h: self.q,
+            k: self.p,
+            L: (true_anomaly_from_eccentric_anomaly(e2.sqrt(), F - long_per) + long_per).rem_euclid(2.0 * PI),
+        }
+    }
+
+    pub fn from_rv(mu: f64, body_state: &BodyState) -> Self {
+        Equinoctial::from_modified(&ModifiedEquinoctial::from_rv(mu, body_state))
+    }
+
+    #[cfg(test)]
+    pub fn to_rv(&self, mu: f64) -> BodyState {
+        self.to_modified().to_rv(mu)
+    }
+}
+
+impl fmt::Display for ModifiedEquinoctial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "MEE {{ p: {:.6}, f: {:.6}, g: {:.6}, h: {:.6}, k: {:.6}, true_long: {:.6} }}",
+            self.p, self.f, self.g, self.h, self.k, self.L.to_degrees()
+        )
+    }
+}
+
+impl fmt::Display for Equinoctial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "EQ {{ a: {:.6}, h: {:.6}, k: {:.6}, p: {:.6}, q: {:.6}, mean_long: {:.6} }}",
+            self.a, self.h, self.k, self.p, self.q, self.λ.to_degrees()
+        )
+    }
+}
+
+// Below these, ω (and Ω) swing around too much under perturbation to be worth showing. Phobos sits at e = 0.015.
+const NEAR_CIRCULAR: f64 = 0.02;
+const NEAR_EQUATORIAL: f64 = 0.02;
+
+// Whichever element set is well defined for a state
+pub enum Elements {
+    Classical(OE),
+    Equinoctial(Equinoctial),
+    ModifiedEquinoctial(ModifiedEquinoctial),
+}
+
+impl Elements {
+    // Classical elements unless the orbit is near circular or near (prograde) equatorial, then equinoctial elements
+    // for ellipses and modified equinoctial elements otherwise
+    pub fn from_rv(mu: f64, body_state: &BodyState) -> Self {
+        let oe = oe_from_rv(mu, body_state);
+        if oe.e > NEAR_CIRCULAR && (oe.i > PI / 2.0 || oe.i.sin() > NEAR_EQUATORIAL) {
+            Elements::Classical(oe)
+        } else if oe.e < 1.0 {
+            Elements::Equinoctial(Equinoctial::from_rv(mu, body_state))
+        } else {
+            Elements::ModifiedEquinoctial(ModifiedEquinoctial::from_rv(mu, body_state))
+        }
+    }
+}
+
+impl fmt::Display for Elements {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Elements::Classical(oe) => oe.fmt(f),
+            // With e and i alongside, which are still well defined when Ω and ω aren't
+            Elements::Equinoctial(eq) => {
+                let mee = eq.to_modified();
+                write!(f, "{} (e: {:.6}, i: {:.6})", eq, mee.e(), mee.i().to_degrees())
+            }
+            Elements::ModifiedEquinoctial(mee) => write!(f, "{} (e: {:.6}, i: {:.6})", mee, mee.e(), mee.i().to_degrees()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use crate::keplerian::{rv_from_oe, OE};
+    use crate::BodyState;
+    use super::*;
+
+    const MU_EARTH: f64 = 3.986004418e14;
+
+    fn assert_states_eq(a: &BodyState, b: &BodyState, what: &str) {
+        assert!(a[0].abs_diff_eq(b[0], 1e-9 * b[0].length()), "{}: {} vs {}", what, a[0], b[0]);
+        assert!(a[1].abs_diff_eq(b[1], 1e-9 * b[1].length()), "{}: {} vs {}", what, a[1], b[1]);
+    }
+
+    // f = 1 rad is short of the asymptotes of every hyperbola here
+    fn state(a: f64, e: f64, i: f64) -> BodyState {
+        rv_from_oe(MU_EARTH, &OE { a, e, i, f: 1.0, ω: 1.2, Ω: 0.7 })
+    }
+
+    #[test]
+    fn round_trips() {
+        let orbits = [
+            ("circular", state(7000e3, 0.0, 0.9), true),
+            ("equatorial", state(9000e3, 0.3, 0.0), true),
+            ("circular equatorial", state(42164e3, 0.0, 0.0), true),
+            ("retrograde", state(12000e3, 0.2, 179f64.to_radians()), true),
+            ("hyperbolic", state(-20000e3, 1.5, 0.4), false),
+        ];
+        for (what, rv, elliptic) in orbits {
+            assert_states_eq(&ModifiedEquinoctial::from_rv(MU_EARTH, &rv).to_rv(MU_EARTH), &rv, what);
+            if elliptic {
+                assert_states_eq(&Equinoctial::from_rv(MU_EARTH, &rv).to_rv(MU_EARTH), &rv, what);
+            }
+        }
+    }
+
+    // Just either side of NEAR_CIRCULAR and NEAR_EQUATORIAL, the set Elements picks still gives the state back
+    #[test]
+    fn switch_thresholds() {
+        let just = |x: f64, above: bool| if above { x * 1.05 } else { x * 0.95 };
+        let mut cases = Vec::new();
+        for above in [false, true] {
+            cases.push((state(8000e3, just(NEAR_CIRCULAR, above), 0.9), above));
+            cases.push((state(8000e3, 0.3, just(NEAR_EQUATORIAL, above).asin()), above));
+            cases.push((state(8000e3, 0.3, PI - just(NEAR_EQUATORIAL, above).asin()), true)); // Retrograde is always classical
+        }
+        for (rv, classical) in cases {
+            let back = match Elements::from_rv(MU_EARTH, &rv) {
+                Elements::Classical(oe) => {
+                    assert!(classical, "{}", oe);
+                    rv_from_oe(MU_EARTH, &oe)
+                }
+                Elements::Equinoctial(eq) => {
+                    assert!(!classical, "{}", eq);
+                    eq.to_rv(MU_EARTH)
+                }
+                Elements::ModifiedEquinoctial(mee) => panic!("elliptic orbit gave {}", mee),
+            };
+            assert_states_eq(&back, &rv, "switch");
+        }
+        let hyperbolic = state(-20000e3, 1.0 + just(NEAR_CIRCULAR, false), 0.0);
+        assert!(matches!(Elements::from_rv(MU_EARTH, &hyperbolic), Elements::ModifiedEquinoctial(_)));
+    }
+
+    // Equinoctial sets are shown with the e and i they hold
+    #[test]
+    fn display_shows_e_and_i() {
+        let text = Elements::from_rv(MU_EARTH, &state(7000e3, 0.01, 0.9)).to_string();
+        assert!(text.starts_with("EQ") && text.ends_with(&format!("(e: 0.010000, i: {:.6})", 0.9f64.to_degrees())), "{}", text);
+        let text = Elements::from_rv(MU_EARTH, &state(-20000e3, 1.5, 0.0)).to_string();
+        assert!(text.starts_with("MEE") && text.ends_with("(e: 1.500000, i: 0.000000)"), "{}", text);
+    }
+}
@@ -0,0 +1,206 @@
+use std::f64::consts::PI;
+use bevy_math::{DMat3, DVec3};
+use crate::time::{step_to_epoch, TimeScale};
+use crate::{BodyInfo, BodyState, StateKeeper};
+// Reference frames, and transforms of states between them. Everything in the simulation is propagated in ICRF
+// (equatorial, +Z the Earth's pole) centered on the Sun, so each frame is described by its origin, orientation and
+// rotation rate relative to that.
+
+// Obliquity of the ecliptic at J2000 (IAU 2006), 84381.406 arcseconds
+pub const OBLIQUITY_J2000: f64 = 0.40909262775014904;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Frame {
+    Icrf,
+    EclipticJ2000,
+    // Centered on the body, +Z along its pole (tilt) and +X toward the ascending node of its equator on the ICRF equator
+    BodyInertial(u32),
+    // BodyInertial, rotated about the pole by the body's prime meridian angle
+    BodyFixed(u32),
+    // Centered on the barycenter of a primary and secondary, +X from primary to secondary and +Z along their relative
+    // angular momentum, rotating with the secondary
+    Synodic(u32, u32),
+}
+
+// A frame at an instant: the ICRF state of its origin, the rotation taking frame axes to ICRF axes, and its angular
+// velocity in ICRF
+pub struct FrameState {
+    pub origin: BodyState,
+    pub rotation: DMat3,
+    pub angular_velocity: DVec3,
+}
+
+// Axes of a body's equatorial frame, as the columns of a rotation to ICRF. The node is at α0 + 90°, which for a pole
+// along ICRF +Z (α0 = 0, the Earth) is +Y.
+pub fn body_equatorial_rotation(pole: DVec3) -> DMat3 {
+    let z = pole.normalize();
+    let node = DVec3::Z.cross(z);
+    let x = if node.length() > 1e-9 { node.normalize() } else { DVec3::Y };
+    DMat3::from_cols(x, z.cross(x), z)
+}
+
+// Angle of the body's prime meridian from the ascending node of its equator on the ICRF equator, t TDB seconds after
+// J2000. This is the IAU model's W = W0 + Ẇ d, without the periodic terms some moons have.
+pub fn prime_meridian_angle(info: &BodyInfo, t: f64) -> f64 {
+    (info.prime_meridian + info.rotational_rate * t).rem_euclid(2.0 * PI)
+}
+
+impl Frame {
+    pub fn at(&self, state_keeper: &StateKeeper, step: u32) -> FrameState {
+        let states = state_keeper.state.get(&step).unwrap();
+        match *self {
+            Frame::Icrf => FrameState {
+                origin: [DVec3::ZERO, DVec3::ZERO],
+                rotation: DMat3::IDENTITY,
+                angular_velocity: DVec3::ZERO,
+            },
+            Frame::EclipticJ2000 => FrameState {
+                origin: [DVec3::ZERO, DVec3::ZERO],
+                rotation: DMat3::from_rotation_x(OBLIQUITY_J2000),
+                angular_velocity: DVec3::ZERO,
+            },
+            Frame::BodyInertial(body) => FrameState {
+                origin: *states.get(&body).unwrap(),
+                rotation: body_equatorial_rotation(state_keeper.info.get(&body).unwrap().tilt),
+                angular_velocity: DVec3::ZERO,
+            },
+            Frame::BodyFixed(body) => {
+                let info = state_keeper.info.get(&body).unwrap();
+                let equatorial = body_equatorial_rotation(info.tilt);
+                let W = prime_meridian_angle(info, step_to_epoch(state_keeper, step).seconds(TimeScale::Tdb));
+                FrameState {
+                    origin: *states.get(&body).unwrap(),
+                    rotation: equatorial * DMat3::from_rotation_z(W),
+                    angular_velocity: info.tilt.normalize() * info.rotational_rate,
+                }
+            }
+            Frame::Synodic(primary, secondary) => {
+                let p = states.get(&primary).unwrap();
+                let s = states.get(&secondary).unwrap();
+                let mu_p = state_keeper.info.get(&primary).unwrap().mu;
+                let mu_s = state_keeper.info.get(&secondary).unwrap().mu;
+                let r = s[0] - p[0];
+                let v = s[1] - p[1];
+                let h = r.cross(v);
+                let x = r.normalize();
+                let z = h.normalize();
+                FrameState {
+                    origin: [
+                        (p[0] * mu_p + s[0] * mu_s) / (mu_p + mu_s),
+                        (p[1] * mu_p + s[1] * mu_s) / (mu_p + mu_s),
+                    ],
+                    rotation: DMat3::from_cols(x, z.cross(x), z),
+                    angular_velocity: h / r.length_squared(),
+                }
+            }
+        }
+    }
+
+    // State in this frame (positions relative to its origin, velocities as seen by an observer rotating with it) to ICRF.
+    // Only the tests go this way so far.
+    #[cfg(test)]
+    pub fn transform_to_icrf(self, state_keeper: &StateKeeper, step: u32, body_state: &BodyState) -> BodyState {
+        let frame = self.at(state_keeper, step);
+        let r = frame.rotation * body_state[0];
+        [
+            frame.origin[0] + r,
+            frame.origin[1] + frame.rotation * body_state[1] + frame.angular_velocity.cross(r),
+        ]
+    }
+
+    pub fn transform_from_icrf(self, state_keeper: &StateKeeper, step: u32, body_state: &BodyState) -> BodyState {
+        let frame = self.at(state_keeper, step);
+        let r = body_state[0] - frame.origin[0];
+        let v = body_state[1] - frame.origin[1] - frame.angular_velocity.cross(r);
+        let inverse = frame.rotation.transpose();
+        [inverse * r, inverse * v]
+    }
+
+    // Rotate a relative state (say, a body relative to its parent) into this frame's axes at an instant, without
+    // shifting it or removing the frame's rotation. This is what orbital elements are computed from, since they
+    // need inertial velocities.
+    pub fn rotate_from_icrf(&self, state_keeper: &StateKeeper, step: u32, body_state: &BodyState) -> BodyState {
+        let inverse = self.at(state_keeper, step).rotation.transpose();
+        [inverse * body_state[0], inverse * body_state[1]]
+    }
+
+    // The same kind of frame, centered on another body. Synodic frames switch to the body and its parent.
+    pub fn recentered(&self, state_keeper: &StateKeeper, body: u32) -> Frame {
+        let parent = state_keeper.info.get(&body).unwrap().kepler_parent;
+        match *self {
+            Frame::BodyInertial(_) => Frame::BodyInertial(body),
+            Frame::BodyFixed(_) => Frame::BodyFixed(body),
+            Frame::Synodic(_, _) if parent != body => Frame::Synodic(parent, body),
+            Frame::Synodic(_, _) => Frame::BodyInertial(body),
+            frame => frame,
+        }
+    }
+
+    pub fn name(&self, state_keeper: &StateKeeper) -> String {
+        let name = |body: u32| state_keeper.info.get(&body).unwrap().name.clone();
+        match *self {
+            Frame::Icrf => "ICRF".to_string(),
+            Frame::EclipticJ2000 => "Ecliptic J2000".to_string(),
+            Frame::BodyInertial(body) => format!("{} inertial", name(body)),
+            Frame::BodyFixed(body) => format!("{} fixed", name(body)),
+            Frame::Synodic(primary, secondary) => format!("{}-{} synodic", name(primary), name(secondary)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use bevy_math::{DMat3, DVec3};
+    use crate::bodies_init::planets_info;
+    use crate::StateKeeper;
+    use super::{body_equatorial_rotation, prime_meridian_angle, Frame};
+
+    // Angle of a direction in the ICRF equator, eastward from +Y (the node at α0 + 90° for α0 = 0)
+    fn from_y(direction: DVec3) -> f64 {
+        (-direction.x).atan2(direction.y).rem_euclid(2.0 * PI)
+    }
+
+    #[test]
+    fn earth_prime_meridian_at_j2000() {
+        let (_, earth) = planets_info().into_iter().find(|(_, info)| info.name == "Earth").unwrap();
+        let meridian = (body_equatorial_rotation(earth.tilt) * DMat3::from_rotation_z(prime_meridian_angle(&earth, 0.0))).x_axis;
+        assert!(meridian.z.abs() < 1e-12);
+        assert!((from_y(meridian) - 190.147f64.to_radians()).abs() < 1e-12, "{} deg", from_y(meridian).to_degrees());
+    }
+
+    // A pole a hair off +Z toward α0 = 0 has its node at +Y, so the fallback for the pole exactly on +Z agrees with it
+    #[test]
+    fn node_is_continuous_at_the_icrf_pole() {
+        let nearly = body_equatorial_rotation(DVec3::new(1e-6, 0.0, 1.0));
+        let exactly = body_equatorial_rotation(DVec3::Z);
+        assert!(nearly.x_axis.abs_diff_eq(exactly.x_axis, 1e-9));
+        assert!(nearly.y_axis.abs_diff_eq(exactly.y_axis, 1e-6));
+    }
+
+    // Into every kind of frame and back out again gives the same ICRF state, and a point standing still on Earth's
+    // surface moves at its rotational speed
+    #[test]
+    fn icrf_round_trip() {
+        let mut app = crate::tests::headless_app();
+        app.update();
+        let state_keeper = app.world().resource::<StateKeeper>();
+        let (earth, luna, step) = (3, 9, 100);
+        let state = *state_keeper.state.get(&step).unwrap().get(&luna).unwrap();
+        let frames = [
+            Frame::Icrf, Frame::EclipticJ2000, Frame::BodyInertial(earth), Frame::BodyFixed(earth), Frame::Synodic(earth, luna),
+        ];
+        for frame in frames {
+            let [r, v] = frame.transform_to_icrf(state_keeper, step, &frame.transform_from_icrf(state_keeper, step, &state));
+            assert!(r.abs_diff_eq(state[0], 1e-4), "{} vs {}", r, state[0]);
+            assert!(v.abs_diff_eq(state[1], 1e-9), "{} vs {}", v, state[1]);
+        }
+
+        let info = state_keeper.info.get(&earth).unwrap();
+        let earth_state = *state_keeper.state.get(&step).unwrap().get(&earth).unwrap();
+        let [r, v] = Frame::BodyFixed(earth).transform_to_icrf(state_keeper, step, &[DVec3::X * info.radius, DVec3::ZERO]);
+        assert!(((r - earth_state[0]).length() - info.radius).abs() < 1e-3);
+        let speed = (v - earth_state[1]).length();
+        assert!((speed - info.radius * info.rotational_rate).abs() < 1e-9, "{} m/s", speed);
+    }
+}
@@ -0,0 +1,335 @@
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fmt;
+use bevy::prelude::*;
+use bevy::prelude::Color;
+use bevy::render::mesh::PrimitiveTopology;
+use bevy_math::{DMat3, DVec3};
+use chrono::{DateTime, Duration, Utc};
+use plotters::element::{BitMapElement, Circle, Rectangle};
+use plotters::prelude::*;
+use plotters::style::Color as _;
+use crate::camera::CameraState;
+use crate::frames::prime_meridian_angle;
+use crate::keplerian::{mean_anomaly_from_true_anomaly, rv_from_oe, true_anomaly_from_mean_anomaly, OE};
+use crate::time::{step_to_date, Epoch, TimeScale};
+use crate::StateKeeper;
+// Ground tracks: where an orbit around a body passes over its surface, in the body's rotating (body fixed) frame.
+// Orbits are given as elements in the body's equatorial (BodyInertial) frame and propagated analytically with the
+// secular J2 drift of Ω, ω and M, which along with the body's spin is what walks the track around in longitude.
+
+pub struct GroundTrack {
+    pub times: Vec<f64>, // Seconds since start
+    pub positions: Vec<DVec3>, // Body fixed
+    pub latitudes: Vec<f64>,
+    pub longitudes: Vec<f64>, // East, in (-π, π]
+    pub node_longitudes: Vec<f64>, // At each ascending equator crossing
+}
+
+// Where each cell of a latitude/longitude grid was in view from the orbit
+pub struct Coverage {
+    pub resolution: f64, // Cell size, in radians of latitude and longitude
+    pub min_elevation: f64,
+    pub visits: Vec<Vec<u32>>, // [latitude row][longitude column]
+    pub fraction: f64, // Of the surface area seen at least once
+    pub mean_revisit: f64, // Seconds between visits, averaged over the cells seen more than once
+    pub max_revisit: f64, // Longest gap between visits of any cell
+}
+
+// Secular rates of Ω, ω and the mean anomaly (on top of the mean motion) from J2
+// Vallado, Fundamentals of Astrodynamics and Applications, 9-41
+pub fn j2_secular_rates(mu: f64, radius: f64, j2: f64, oe: &OE) -> (f64, f64, f64) {
+    let n = (mu / oe.a.powi(3)).sqrt();
+    let k = 1.5 * n * j2 * (radius / oe.p()).powi(2);
+    let cos_i = oe.i.cos();
+    (
+        -k * cos_i,
+        0.5 * k * (5.0 * cos_i * cos_i - 1.0),
+        0.5 * k * (1.0 - oe.e * oe.e).sqrt() * (3.0 * cos_i * cos_i - 1.0),
+    )
+}
+
+fn latitude_longitude(r: DVec3) -> (f64, f64) {
+    ((r.z / r.length()).asin(), r.y.atan2(r.x))
+}
+
+fn unit_from_latitude_longitude(latitude: f64, longitude: f64) -> DVec3 {
+    DVec3::new(latitude.cos() * longitude.cos(), latitude.cos() * longitude.sin(), latitude.sin())
+}
+
+impl GroundTrack {
+    // Sample every interval seconds between start and end, for an (elliptic) orbit around body with elements oe at start
+    pub fn new(state_keeper: &StateKeeper, body: u32, oe: &OE, start: DateTime<Utc>, end: DateTime<Utc>, interval: f64) -> Self {
+        let info = state_keeper.info.get(&body).unwrap();
+        let (dΩ, dω, dM) = j2_secular_rates(info.mu, info.radius, info.j2, oe);
+        let n = (info.mu / oe.a.powi(3)).sqrt();
+        let M0 = mean_anomaly_from_true_anomaly(oe.e, oe.f);
+        let t0 = Epoch::from_utc(start).seconds(TimeScale::Tdb);
+        let duration = (end - start).num_milliseconds() as f64 / 1000.0;
+
+        let mut track = GroundTrack {
+            times: Vec::new(), positions: Vec::new(), latitudes: Vec::new(), longitudes: Vec::new(), node_longitudes: Vec::new(),
+        };
+        for k in 0..=(duration / interval).max(0.0) as usize {
+            let t = k as f64 * interval;
+            let oe_t = OE {
+                a: oe.a,
+                e: oe.e,
+                i: oe.i,
+                f: true_anomaly_from_mean_anomaly(oe.e, M0 + (n + dM) * t),
+                ω: oe.ω + dω * t,
+                Ω: oe.Ω + dΩ * t,
+            };
+            let r_equatorial = rv_from_oe(info.mu, &oe_t)[0];
+            let r = DMat3::from_rotation_z(-prime_meridian_angle(info, t0 + t)) * r_equatorial;
+            let (latitude, longitude) = latitude_longitude(r);
+
+            // Ascending node, interpolated between the samples either side of it
+            if let Some(previous) = track.positions.last() {
+                if previous.z < 0.0 && r.z >= 0.0 {
+                    let fraction = -previous.z / (r.z - previous.z);
+                    track.node_longitudes.push(latitude_longitude(previous.lerp(r, fraction)).1);
+                }
+            }
+            track.times.push(t);
+            track.positions.push(r);
+            track.latitudes.push(latitude);
+            track.longitudes.push(longitude);
+        }
+        track
+    }
+
+    // Mean eastward shift of the ascending node from one orbit to the next, in (-π, π]
+    pub fn node_drift_per_orbit(&self) -> Option<f64> {
+        if self.node_longitudes.len() < 2 {
+            return None;
+        }
+        let shifts: Vec<f64> = self.node_longitudes.windows(2)
+            .map(|w| (w[1] - w[0] + PI).rem_euclid(2.0 * PI) - PI)
+            .collect();
+        Some(shifts.iter().sum::<f64>() / shifts.len() as f64)
+    }
+
+    // Which cells of a resolution sized latitude/longitude grid come into view, from anywhere the spacecraft is at
+    // least min_elevation above the horizon, and how often
+    pub fn coverage(&self, radius: f64, resolution: f64, min_elevation: f64) -> Coverage {
+        let rows = (PI / resolution).round() as usize;
+        let columns = (2.0 * PI / resolution).round() as usize;
+        let centers: Vec<Vec<DVec3>> = (0..rows).map(|row| {
+            let latitude = -PI / 2.0 + (row as f64 + 0.5) * resolution;
+            (0..columns).map(|column| unit_from_latitude_longitude(latitude, -PI + (column as f64 + 0.5) * resolution)).collect()
+        }).collect();
+
+        let mut visits = vec![vec![0u32; columns]; rows];
+        let mut last_seen: Vec<Vec<Option<usize>>> = vec![vec![None; columns]; rows];
+        let mut gaps = vec![vec![0.0f64; columns]; rows];
+        let mut max_revisit = 0.0f64;
+        for (k, r) in self.positions.iter().enumerate() {
+            // Earth central angle from the sub-satellite point to the edge of the footprint
+            let half_angle = (radius / r.length() * min_elevation.cos()).clamp(-1.0, 1.0).acos() - min_elevation;
+            if half_angle <= 0.0 {
+                continue;
+            }
+            let cos_half_angle = half_angle.cos();
+            let sub_satellite = r.normalize();
+            let lowest = ((self.latitudes[k] - half_angle + PI / 2.0) / resolution).floor().max(0.0) as usize;
+            let highest = (((self.latitudes[k] + half_angle + PI / 2.0) / resolution).ceil() as usize).min(rows);
+            for row in lowest..highest {
+                for column in 0..columns {
+                    if centers[row][column].dot(sub_satellite) < cos_half_angle {
+                        continue;
+                    }
+                    match last_seen[row][column] {
+                        Some(previous) if previous + 1 == k => {}
+                        Some(previous) => {
+                            let gap = self.times[k] - self.times[previous];
+                            gaps[row][column] += gap;
+                            max_revisit = max_revisit.max(gap);
+                            visits[row][column] += 1;
+                        }
+                        None => visits[row][column] += 1,
+                    }
+                    last_seen[row][column] = Some(k);
+                }
+            }
+        }
+
+        // Cells shrink toward the poles, so weight by cos(latitude)
+        let (mut seen_area, mut total_area) = (0.0, 0.0);
+        let (mut revisit_sum, mut revisited) = (0.0, 0);
+        for row in 0..rows {
+            let weight = (-PI / 2.0 + (row as f64 + 0.5) * resolution).cos();
+            for column in 0..columns {
+                total_area += weight;
+                if visits[row][column] > 0 {
+                    seen_area += weight;
+                }
+                if visits[row][column] > 1 {
+                    revisit_sum += gaps[row][column] / (visits[row][column] - 1) as f64;
+                    revisited += 1;
+                }
+            }
+        }
+
+        Coverage {
+            resolution,
+            min_elevation,
+            visits,
+            fraction: seen_area / total_area,
+            mean_revisit: if revisited > 0 { revisit_sum / revisited as f64 } else { f64::NAN },
+            max_revisit,
+        }
+    }
+
+    // Equirectangular map, longitude -180 to 180 left to right, over the body's texture if it's loaded. Cells never in
+    // view are shaded, and ascending nodes are marked.
+    pub fn plot(&self, path: &str, name: &str, texture: Option<&Image>, coverage: &Coverage) -> Result<(), Box<dyn Error>> {
+        let root = BitMapBackend::new(path, (1500, 820)).into_drawing_area();
+        root.fill(&WHITE)?;
+        let mut chart = ChartBuilder::on(&root)
+            .caption(format!("{} ground track", name), ("sans-serif", 24))
+            .margin(10)
+            .x_label_area_size(40)
+            .y_label_area_size(50)
+            .build_cartesian_2d(-180.0..180.0, -90.0..90.0)?;
+
+        if let Some(texture) = texture {
+            let (width, height) = chart.plotting_area().dim_in_pixel();
+            let mut buffer = Vec::with_capacity((width * height * 3) as usize);
+            for y in 0..height {
+                for x in 0..width {
+                    let u = (x * texture.width() / width).min(texture.width() - 1);
+                    let v = (y * texture.height() / height).min(texture.height() - 1);
+                    let color = texture.get_color_at(u, v).map(|c| c.to_srgba()).unwrap_or(Srgba::BLACK);
+                    buffer.extend([color.red, color.green, color.blue].map(|c| (c * 255.0) as u8));
+                }
+            }
+            if let Some(element) = BitMapElement::with_owned_buffer((-180.0, 90.0), (width, height), buffer) {
+                chart.plotting_area().draw(&element)?;
+            }
+        }
+
+        let cell = coverage.resolution.to_degrees();
+        chart.draw_series(coverage.visits.iter().enumerate().flat_map(|(row, columns)| {
+            columns.iter().enumerate().filter(|(_, &visits)| visits == 0).map(move |(column, _)| {
+                let (x, y) = (-180.0 + column as f64 * cell, -90.0 + row as f64 * cell);
+                Rectangle::new([(x, y), (x + cell, y + cell)], BLACK.mix(0.35).filled())
+            })
+        }))?;
+
+        chart.configure_mesh()
+            .x_desc("Longitude (deg)")
+            .y_desc("Latitude (deg)")
+            .x_labels(13)
+            .y_labels(7)
+            .light_line_style(TRANSPARENT)
+            .draw()?;
+
+        // Break the line wherever it wraps from one edge of the map to the other
+        let mut segment: Vec<(f64, f64)> = Vec::new();
+        for (&latitude, &longitude) in self.latitudes.iter().zip(&self.longitudes) {
+            let point = (longitude.to_degrees(), latitude.to_degrees());
+            if segment.last().is_some_and(|last| (last.0 - point.0).abs() > 180.0) {
+                chart.draw_series(LineSeries::new(segment.drain(..), RED.stroke_width(2)))?;
+            }
+            segment.push(point);
+        }
+        chart.draw_series(LineSeries::new(segment, RED.stroke_width(2)))?;
+        chart.draw_series(self.node_longitudes.iter().map(|longitude| Circle::new((longitude.to_degrees(), 0.0), 5, YELLOW.filled())))?;
+
+        root.present()?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.1}% of the surface seen above {:.0} deg elevation, mean revisit {:.2} h, longest gap {:.2} h",
+            self.fraction * 100.0, self.min_elevation.to_degrees(), self.mean_revisit / 3600.0, self.max_revisit / 3600.0
+        )
+    }
+}
+
+#[derive(Component)]
+pub struct GroundTrackDisplay {}
+
+// Press G to draw the scenario's ground track study, or a day of the parking orbit around the focused body (as flown
+// from the launch site if that's the departure body), on its sphere and in {name}_groundtrack.png, and log the node
+// drift and coverage
+pub fn show_focused_ground_track(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    state_keeper: Res<StateKeeper>,
+    camera_state: Single<&CameraState>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    images: Res<Assets<Image>>,
+    asset_server: Res<AssetServer>,
+    displayed: Query<Entity, With<GroundTrackDisplay>>,
+) {
+    if !keys.just_pressed(KeyCode::KeyG) {
+        return;
+    }
+    for entity in displayed.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let (body, oe, start, end) = match &state_keeper.scenario.ground_track {
+        Some(study) => {
+            // The scenario was validated, so the body is in the catalog
+            let body = *state_keeper.info.iter().find(|(_, info)| info.name == study.body).unwrap().0;
+            let day = |days: f64| state_keeper.epoch.add_seconds(days * 86400.0).to_utc();
+            (body, study.orbit.clone(), day(study.window_days.0), day(study.window_days.1))
+        }
+        None => {
+            let body = camera_state.focused;
+            let radius = state_keeper.info.get(&body).unwrap().radius;
+            let orbit = &state_keeper.departure_orbit;
+            // The launch site only limits the inclination of orbits around the body it's on
+            let i = if state_keeper.info.get(&body).unwrap().name == state_keeper.scenario.transfer.departure {
+                orbit.i.max(state_keeper.launch_site.minimum_inclination())
+            } else {
+                orbit.i
+            };
+            let oe = OE { a: (orbit.rp(radius) + orbit.ra(radius)) / 2.0, e: orbit.e, i, f: 0.0, ω: 0.0, Ω: 0.0 };
+            let start = step_to_date(&state_keeper, state_keeper.current_step);
+            (body, oe, start, start + Duration::days(1))
+        }
+    };
+    let info = state_keeper.info.get(&body).unwrap();
+    let track = GroundTrack::new(&state_keeper, body, &oe, start, end, 30.0);
+    let coverage = track.coverage(info.radius, 2f64.to_radians(), 10f64.to_radians());
+
+    let texture = images.get(&asset_server.load::<Image>(format!("textures/{}", info.texture)));
+    let path = format!("{}_groundtrack.png", info.name.to_lowercase());
+    if let Err(e) = track.plot(&path, &info.name, texture, &coverage) {
+        error!("Couldn't plot {} ground track: {}", info.name, e);
+    }
+    match track.node_drift_per_orbit() {
+        Some(drift) => info!("{} ground track: node drifts {:.2} deg per orbit, {}", info.name, drift.to_degrees(), coverage),
+        None => info!("{} ground track: {}", info.name, coverage),
+    }
+
+    // On the sphere, just above the surface, as a child of the body's mesh so it turns with it. The mesh is turned half
+    // a turn about its pole from the body fixed frame (see rotate_bodies).
+    let Some(body_display_id) = info.body_display_id else { return };
+    let mut mesh = Mesh::new(PrimitiveTopology::LineStrip, Default::default());
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_POSITION,
+        track.positions.iter().map(|r| (r.normalize() * info.radius * 1.01).as_vec3()).collect::<Vec<Vec3>>(),
+    );
+    let line = commands.spawn((
+        Mesh3d(meshes.add(mesh)),
+        MeshMaterial3d(materials.add(StandardMaterial {
+            base_color: Color::WHITE,
+            emissive: LinearRgba::new(1.0, 0.1, 0.1, 1.0),
+            unlit: true,
+            ..default()
+        })),
+        Transform::from_rotation(Quat::from_rotation_z(-std::f32::consts::PI)),
+        GroundTrackDisplay {},
+    )).id();
+    commands.entity(body_display_id).add_child(line);
+}
@@ -1,21 +1,200 @@
+use std::f64::consts::PI;
+use std::fmt;
 use bevy::prelude::*;
-use bevy_math::{DMat3, DVec3};
-use crate::keplerian::{oe_from_rv, pqw_to_inertial_rot, OE};
-use crate::StateKeeper;
+use bevy_math::DVec3;
+use serde::{Deserialize, Serialize};
+use crate::keplerian::{oe_from_rv, propagate_rv, rv_from_oe, OE};
+use crate::{BodyState, StateKeeper};
 
-#[derive(Clone)]
+// Closed orbit about a body, given by its periapsis altitude above the surface, eccentricity and inclination
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OrbitGeometry {
+    pub periapsis_altitude: f64,
+    pub e: f64,
+    pub i: f64,
+}
+
+impl OrbitGeometry {
+    pub fn circular(altitude: f64, i: f64) -> Self {
+        OrbitGeometry { periapsis_altitude: altitude, e: 0.0, i }
+    }
+
+    pub fn from_apsides(periapsis_altitude: f64, apoapsis_altitude: f64, radius: f64, i: f64) -> Self {
+        let rp = radius + periapsis_altitude;
+        let ra = radius + apoapsis_altitude;
+        OrbitGeometry { periapsis_altitude, e: (ra - rp) / (ra + rp), i }
+    }
+
+    pub fn rp(&self, radius: f64) -> f64 {
+        radius + self.periapsis_altitude
+    }
+
+    pub fn ra(&self, radius: f64) -> f64 {
+        self.rp(radius) * (1.0 + self.e) / (1.0 - self.e)
+    }
+
+    pub fn periapsis_speed(&self, mu: f64, radius: f64) -> f64 {
+        (mu * (1.0 + self.e) / self.rp(radius)).sqrt()
+    }
+
+    pub fn apoapsis_speed(&self, mu: f64, radius: f64) -> f64 {
+        (mu * (1.0 - self.e) / self.ra(radius)).sqrt()
+    }
+}
+
+impl fmt::Display for OrbitGeometry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.0} km, e {:.3}, i {:.1} deg", self.periapsis_altitude / 1000.0, self.e, self.i.to_degrees())
+    }
+}
+
+// How the spacecraft is captured at the arrival body
+#[derive(Clone, Serialize, Deserialize)]
+pub enum ArrivalMode {
+    // Single propulsive burn at periapsis of the arrival hyperbola into the given orbit
+    Orbit(OrbitGeometry),
+    // Smallest periapsis burn that leaves the spacecraft bound (a parabola's speed at periapsis)
+    CaptureOnly { periapsis_altitude: f64 },
+    // The atmosphere removes the hyperbolic excess on a pass at entry_altitude, leaving an orbit with the target's
+    // apoapsis; the only burn is at that apoapsis, raising periapsis out of the atmosphere to the target orbit
+    Aerocapture { orbit: OrbitGeometry, entry_altitude: f64 },
+}
+
+impl ArrivalMode {
+    // Periapsis altitude of the arrival hyperbola
+    pub fn hyperbola_periapsis_altitude(&self) -> f64 {
+        match self {
+            ArrivalMode::Orbit(orbit) => orbit.periapsis_altitude,
+            ArrivalMode::CaptureOnly { periapsis_altitude } => *periapsis_altitude,
+            ArrivalMode::Aerocapture { entry_altitude, .. } => *entry_altitude,
+        }
+    }
+
+    // Inclination of the final orbit, relative to the arrival body's equator. A bare capture has no preference, and
+    // takes the lowest inclination the asymptote allows.
+    pub fn inclination(&self) -> f64 {
+        match self {
+            ArrivalMode::Orbit(orbit) => orbit.i,
+            ArrivalMode::CaptureOnly { .. } => 0.0,
+            ArrivalMode::Aerocapture { orbit, .. } => orbit.i,
+        }
+    }
+
+    // Propulsive Δv needed at arrival, given the speed at periapsis of the arrival hyperbola
+    pub fn capture_dv(&self, mu: f64, radius: f64, vp_hyperbola: f64) -> f64 {
+        match self {
+            ArrivalMode::Orbit(orbit) => (vp_hyperbola - orbit.periapsis_speed(mu, radius)).abs(),
+            ArrivalMode::CaptureOnly { periapsis_altitude } => {
+                (vp_hyperbola - (2.0 * mu / (radius + periapsis_altitude)).sqrt()).max(0.0)
+            }
+            ArrivalMode::Aerocapture { orbit, entry_altitude } => {
+                let ra = orbit.ra(radius);
+                let exit = OrbitGeometry::from_apsides(*entry_altitude, ra - radius, radius, orbit.i);
+                (orbit.apoapsis_speed(mu, radius) - exit.apoapsis_speed(mu, radius)).abs()
+            }
+        }
+    }
+}
+
+impl fmt::Display for ArrivalMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArrivalMode::Orbit(orbit) => write!(f, "capture into {}", orbit),
+            ArrivalMode::CaptureOnly { periapsis_altitude } => write!(f, "capture only at {:.0} km", periapsis_altitude / 1000.0),
+            ArrivalMode::Aerocapture { orbit, entry_altitude } => write!(f, "aerocapture at {:.0} km into {}", entry_altitude / 1000.0, orbit),
+        }
+    }
+}
+
+// Launch site on the departure body: its latitude and the range of launch azimuths (clockwise from north) it allows
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LaunchSite {
+    pub latitude: f64,
+    pub azimuth_min: f64,
+    pub azimuth_max: f64,
+}
+
+impl LaunchSite {
+    // Inclination reached by a direct ascent on the given azimuth, cos(i) = cos(latitude) sin(azimuth)
+    pub fn inclination_for_azimuth(&self, azimuth: f64) -> f64 {
+        (self.latitude.cos() * azimuth.sin()).clamp(-1.0, 1.0).acos()
+    }
+
+    // Lowest parking orbit inclination reachable from this site without a plane change. Launching due east gives
+    // i = |latitude|; otherwise the allowed azimuth closest to east sets the limit.
+    pub fn minimum_inclination(&self) -> f64 {
+        if self.azimuth_min <= PI / 2.0 && PI / 2.0 <= self.azimuth_max {
+            self.latitude.abs()
+        } else {
+            self.inclination_for_azimuth(self.azimuth_min).min(self.inclination_for_azimuth(self.azimuth_max))
+        }
+    }
+}
+
+impl fmt::Display for LaunchSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "lat {:.1} deg, az {:.0}-{:.0} deg",
+            self.latitude.to_degrees(), self.azimuth_min.to_degrees(), self.azimuth_max.to_degrees()
+        )
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Interplanetary {
     pub body0: u32,
     pub body1: u32,
     pub body2: u32,
+    pub departure_step: u32,
+    pub arrival_step: u32,
     pub oe0: OE,
     pub oe1: OE,
     pub oe2: OE,
     pub dv1: f64,
     pub dv2: f64,
+    pub v_inf1: f64,
+    pub v_inf2: f64,
+    pub departure_orbit: OrbitGeometry,
+    pub arrival_mode: ArrivalMode,
+    pub dla: f64, // Declination of the departure asymptote, relative to body1's equator
+    pub parking_inclination: f64, // Inclination actually flown, after the launch site's limits are applied
+    pub plane_change: f64, // Extra plane change needed when |dla| exceeds the parking inclination
+    pub plane_change_dv: f64, // Part of dv1 spent on that plane change
 }
 
-pub fn interplanetary(state_keeper: &ResMut<StateKeeper>, departure_step: u32, arrival_step: u32, body1: u32, body2: u32, short: bool) -> Interplanetary {
+impl Interplanetary {
+    pub fn dla_achievable(&self) -> bool {
+        self.plane_change <= 0.0
+    }
+
+    // Heliocentric state of the spacecraft along the Lambert arc at the given step, or None outside the transfer
+    pub fn transfer_state(&self, mu: f64, dt: f64, step: u32) -> Option<BodyState> {
+        if step < self.departure_step || step > self.arrival_step {
+            return None;
+        }
+        Some(propagate_rv(mu, &rv_from_oe(mu, &self.oe0), (step - self.departure_step) as f64 * dt))
+    }
+}
+
+impl fmt::Display for Interplanetary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "dv1: {} (v_inf {}, from {})\ndv2: {} (v_inf {}, {})\ntotal: {}\nDLA: {:.1} deg, parking i: {:.1} deg",
+            self.dv1 as i32, self.v_inf1 as i32, self.departure_orbit,
+            self.dv2 as i32, self.v_inf2 as i32, self.arrival_mode,
+            (self.dv1 + self.dv2) as i32,
+            self.dla.to_degrees(), self.parking_inclination.to_degrees()
+        )?;
+        if !self.dla_achievable() {
+            write!(f, "\nDLA unreachable: plane change {:.1} deg, +{} m/s", self.plane_change.to_degrees(), self.plane_change_dv as i32)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn interplanetary(state_keeper: &StateKeeper, departure_step: u32, arrival_step: u32, body1: u32, body2: u32, short: bool) -> Interplanetary {
     // First, solve for the OE of the transfer orbit
     let delta_step = arrival_step - departure_step;
     let dt = delta_step as f64 * state_keeper.dt;
@@ -34,98 +213,164 @@ pub fn interplanetary(state_keeper: &ResMut<StateKeeper>, departure_step: u32, a
     let v1 = DVec3::from_array(v1);
     let v2 = DVec3::from_array(v2);
 
-    let rp1 = state_keeper.info.get(&body1).unwrap().radius + 180000.0;
-    let rp2 = state_keeper.info.get(&body2).unwrap().radius + 180000.0;
+    let info1 = state_keeper.info.get(&body1).unwrap();
+    let info2 = state_keeper.info.get(&body2).unwrap();
+    let departure_orbit = state_keeper.departure_orbit.clone();
+    let arrival_mode = state_keeper.arrival_mode.clone();
+
+    // The hyperbolas are placed with their periapses at the departure orbit's and arrival mode's periapses
+    let rp1 = departure_orbit.rp(info1.radius);
+    let rp2 = info2.radius + arrival_mode.hyperbola_periapsis_altitude();
+    let parking_inclination = departure_orbit.i.max(state_keeper.launch_site.minimum_inclination());
+
+    let ([oe1,oe2],[vp1,vp2]) = solve_interplanetary_hyperbolas(
+        state_keeper,
+        &Encounter { body: body1, step: departure_step, v: v1, rp: rp1, i: parking_inclination },
+        &Encounter { body: body2, step: arrival_step, v: v2, rp: rp2, i: arrival_mode.inclination() },
+    );
 
-    let ([oe1,oe2],[vp1,vp2]) = solve_interplanetary_hyperbolas(state_keeper, departure_step, arrival_step, body1, body2, &v1, &v2, rp1, rp2);
+    let v_inf1_vec = v1 - state_keeper.state.get(&departure_step).unwrap().get(&body1).unwrap()[1];
+    let v_inf2 = (v2 - state_keeper.state.get(&arrival_step).unwrap().get(&body2).unwrap()[1]).length();
 
-    let v_circ_1 = (state_keeper.info.get(&body1).unwrap().mu / rp1).sqrt();
-    let v_circ_2 = (state_keeper.info.get(&body2).unwrap().mu / rp2).sqrt();
+    // The outgoing asymptote can only lie in the parking plane if its declination is within the parking inclination,
+    // which itself can't be lower than the launch site allows. Otherwise the difference is made up at the injection
+    // burn, combining the plane change with the speed change at periapsis.
+    let dla = v_inf1_vec.normalize().dot(info1.tilt.normalize()).clamp(-1.0, 1.0).asin();
+    let plane_change = (dla.abs() - parking_inclination.min(PI - parking_inclination)).max(0.0);
 
-    let dv1 = (vp1-v_circ_1).abs();
-    let dv2 = (vp2-v_circ_2).abs();
+    let v_park = departure_orbit.periapsis_speed(info1.mu, info1.radius);
+    let dv1_coplanar = (vp1 - v_park).abs();
+    let dv1 = (vp1.powi(2) + v_park.powi(2) - 2.0 * vp1 * v_park * plane_change.cos()).sqrt();
+    let plane_change_dv = dv1 - dv1_coplanar;
+    let dv2 = arrival_mode.capture_dv(info2.mu, info2.radius, vp2);
 
-    Interplanetary {body0: 0, body1, body2, oe0, oe1, oe2, dv1, dv2}
+    Interplanetary {
+        body0: 0, body1, body2, departure_step, arrival_step, oe0, oe1, oe2, dv1, dv2,
+        v_inf1: v_inf1_vec.length(), v_inf2,
+        departure_orbit, arrival_mode,
+        dla, parking_inclination, plane_change, plane_change_dv,
+    }
 }
 
-pub fn solve_interplanetary_hyperbolas(
-    state_keeper: &ResMut<StateKeeper>,
-    departure_step: u32,
-    arrival_step: u32,
-    body1: u32,
-    body2: u32,
-    v1: &DVec3,
-    v2: &DVec3,
-    rp1: f64,
-    rp2: f64,
-) -> ([OE; 2], [f64; 2]) {
-    let r1 = state_keeper.state.get(&departure_step).unwrap().get(&body1).unwrap()[0];
-    let r2 = state_keeper.state.get(&arrival_step).unwrap().get(&body2).unwrap()[0];
-    let v_inf1 = v1 - state_keeper.state.get(&departure_step).unwrap().get(&body1).unwrap()[1];
-    let v_inf2 = v2 - state_keeper.state.get(&arrival_step).unwrap().get(&body2).unwrap()[1];
-    let mu1 = state_keeper.info.get(&body1).unwrap().mu;
-    let mu2 = state_keeper.info.get(&body2).unwrap().mu;
-    let vp1 = (v_inf1.length().powi(2) + 2.0 * mu1 / rp1).sqrt();
-    let vp2 = (v_inf2.length().powi(2) + 2.0 * mu2 / rp2).sqrt();
-    let e1 = 1.0 + rp1 * v_inf1.length().powi(2) / mu1;
-    let e2 = 1.0 + rp2 * v_inf2.length().powi(2) / mu2;
-    let ta1 = 2.0 * ((1.0 / e1).clamp(-1.0, 1.0)).asin();
-    let ta2 = 2.0 * ((1.0 / e2).clamp(-1.0, 1.0)).asin();
-    let r1_pqw = DVec3::new(rp1, 0.0, 0.0);
-    let v1_pqw = DVec3::new(0.0, vp1, 0.0);
-    let r2_pqw = DVec3::new(rp2, 0.0, 0.0);
-    let v2_pqw = DVec3::new(0.0, vp2, 0.0);
-    let u_inf1_pqw = DVec3::new(-(ta1 / 2.0).cos(), (ta1 / 2.0).sin(), 0.0);
-    let u_inf2_pqw = DVec3::new(-(ta2 / 2.0).cos(), (ta2 / 2.0).sin(), 0.0);
-
-    let [Ω1, i1, ω_try1] = (0..360)
-        .map(|z| (z as f64).to_radians())
-        .find_map(|ωt| solve_euler_angles_from_u_inf(u_inf1_pqw, v_inf1.normalize(), ωt).map(|a| [a[0], a[1], ωt]))
-        .unwrap();
-    let h1 = r1.cross(v_inf1).normalize();
-    let n1 = DVec3::Z.cross(h1).normalize();
-    let r1_hat = r1.normalize();
-    let cos_ω1 = n1.dot(r1_hat);
-    let sin_ω1 = h1.cross(n1).normalize().dot(r1_hat);
-    let ω1 = sin_ω1.atan2(cos_ω1);
-    let R1 = pqw_to_inertial_rot(Ω1, ω1, i1);
-
-    let [Ω2, i2, ω_try2] = (0..360)
-        .map(|z| (z as f64).to_radians())
-        .find_map(|ωt| solve_euler_angles_from_u_inf(u_inf2_pqw, v_inf2.normalize(), ωt).map(|a| [a[0], a[1], ωt]))
-        .unwrap();
-    let h2 = r2.cross(v_inf2).normalize();
-    let n2 = DVec3::Z.cross(h2).normalize();
-    let r2_hat = r2.normalize();
-    let cos_ω2 = n2.dot(r2_hat);
-    let sin_ω2 = h2.cross(n2).normalize().dot(r2_hat);
-    let ω2 = sin_ω2.atan2(cos_ω2);
-    let R2 = pqw_to_inertial_rot(Ω2, ω2, i2);
-
-    let rp1_vec = R1 * r1_pqw;
-    let vp1_vec = R1 * v1_pqw;
-    let rp2_vec = R2 * r2_pqw;
-    let vp2_vec = R2 * v2_pqw;
+// One end of a transfer: the body and step where the Lambert arc meets it, the arc's heliocentric velocity there, and
+// the periapsis radius and inclination wanted for the hyperbola about the body
+pub struct Encounter {
+    pub body: u32,
+    pub step: u32,
+    pub v: DVec3,
+    pub rp: f64,
+    pub i: f64,
+}
+
+pub fn solve_interplanetary_hyperbolas(state_keeper: &StateKeeper, departure: &Encounter, arrival: &Encounter) -> ([OE; 2], [f64; 2]) {
+    let v_inf1 = departure.v - state_keeper.state.get(&departure.step).unwrap().get(&departure.body).unwrap()[1];
+    let v_inf2 = arrival.v - state_keeper.state.get(&arrival.step).unwrap().get(&arrival.body).unwrap()[1];
+    let info1 = state_keeper.info.get(&departure.body).unwrap();
+    let info2 = state_keeper.info.get(&arrival.body).unwrap();
+
+    let family1 = HyperbolaFamily::new(info1.mu, departure.rp, v_inf1, true, info1.tilt);
+    let family2 = HyperbolaFamily::new(info2.mu, arrival.rp, v_inf2, false, info2.tilt);
+
+    // Fly the requested inclination when the asymptote allows it, otherwise the lowest inclination plane (θ = 0)
+    let θ1 = family1.angles_for_inclination(departure.i).first().copied().unwrap_or(0.0);
+    let θ2 = family2.angles_for_inclination(arrival.i).first().copied().unwrap_or(0.0);
+    let periapsis1 = family1.periapsis_state(θ1);
+    let periapsis2 = family2.periapsis_state(θ2);
 
     (
         [
-            oe_from_rv(mu1, &[rp1_vec, vp1_vec]),
-            oe_from_rv(mu2, &[rp2_vec, vp2_vec]),
+            oe_from_rv(info1.mu, &periapsis1),
+            oe_from_rv(info2.mu, &periapsis2),
         ],
-        [vp1_vec.length(), vp2_vec.length()],
+        [periapsis1[1].length(), periapsis2[1].length()],
     )
 }
 
-pub fn solve_euler_angles_from_u_inf(u_inf_pqw: DVec3, u_inf: DVec3, ω: f64) -> Option<[f64; 2]> {
-    let A = DMat3::from_axis_angle(DVec3::Z, -ω) * u_inf_pqw;
-    if A.y.abs() < 1e-6 {
-        return None;
-    }
-    let i = u_inf.z / A.y;
-    let Ω = (u_inf.x * A.x - u_inf.y * A.x).atan2(u_inf.x * A.x + u_inf.y * A.y * i.cos());
-    Some([Ω, i])
+// Every hyperbola about a body with periapsis radius rp and hyperbolic excess velocity v_inf. They all share a shape,
+// and differ only by the orientation of their plane, which has to contain v_inf. The family is parameterised by the
+// angle θ the plane normal is rotated about v_inf, with θ = 0 being the plane of lowest inclination relative to pole.
+#[derive(Clone)]
+pub struct HyperbolaFamily {
+    pub mu: f64,
+    pub rp: f64,
+    pub v_inf: DVec3,
+    pub departure: bool, // v_inf is the outgoing asymptote if true, the incoming one if false
+    pub pole: DVec3,
+    h0: DVec3,
 }
 
+impl HyperbolaFamily {
+    pub fn new(mu: f64, rp: f64, v_inf: DVec3, departure: bool, pole: DVec3) -> Self {
+        let u = v_inf.normalize();
+        let pole = pole.normalize();
+        // The lowest inclination plane's normal is the pole projected perpendicular to v_inf. A polar v_inf has every
+        // plane at 90 degrees, so any perpendicular will do.
+        let projected = pole - u * pole.dot(u);
+        let h0 = if projected.length() > 1e-9 {
+            projected.normalize()
+        } else {
+            u.any_orthonormal_vector()
+        };
+        HyperbolaFamily { mu, rp, v_inf, departure, pole, h0 }
+    }
+
+    pub fn e(&self) -> f64 {
+        1.0 + self.rp * self.v_inf.length_squared() / self.mu
+    }
+
+    // True anomaly of the asymptotes
+    pub fn f_inf(&self) -> f64 {
+        (-1.0 / self.e()).acos()
+    }
+
+    // Declination of v_inf above the pole's equator
+    pub fn declination(&self) -> f64 {
+        self.v_inf.normalize().dot(self.pole).clamp(-1.0, 1.0).asin()
+    }
+
+    pub fn plane_normal(&self, θ: f64) -> DVec3 {
+        let u = self.v_inf.normalize();
+        self.h0 * θ.cos() + u.cross(self.h0) * θ.sin()
+    }
+
+    // Values of θ whose plane has inclination i relative to pole. Since the normal's pole component is
+    // cos(θ) cos(declination), there are two (mirror images about the lowest plane) when |declination| < i, one when
+    // they're equal, and none when the asymptote can't be reached at that inclination.
+    pub fn angles_for_inclination(&self, i: f64) -> Vec<f64> {
+        let cos_δ = self.declination().cos();
+        if cos_δ < 1e-9 {
+            return if i.cos().abs() < 1e-9 { vec![0.0] } else { Vec::new() };
+        }
+        let cos_θ = i.cos() / cos_δ;
+        if cos_θ.abs() > 1.0 + 1e-12 {
+            return Vec::new();
+        }
+        let θ = cos_θ.clamp(-1.0, 1.0).acos();
+        if θ < 1e-12 {
+            vec![0.0]
+        } else {
+            vec![θ, -θ]
+        }
+    }
+
+    // Periapsis position and velocity of the member with plane angle θ. With P the periapsis direction and
+    // Q = h x P, the outgoing asymptote is cos(f_inf) P + sin(f_inf) Q and the incoming one -cos(f_inf) P + sin(f_inf) Q,
+    // which inverts to P in terms of v_inf and h x v_inf.
+    pub fn periapsis_state(&self, θ: f64) -> BodyState {
+        let u = self.v_inf.normalize();
+        let h = self.plane_normal(θ);
+        let w = h.cross(u);
+        let f_inf = self.f_inf();
+        let p = if self.departure {
+            u * f_inf.cos() - w * f_inf.sin()
+        } else {
+            -u * f_inf.cos() - w * f_inf.sin()
+        };
+        let q = h.cross(p);
+        let vp = (self.v_inf.length_squared() + 2.0 * self.mu / self.rp).sqrt();
+        [p * self.rp, q * vp]
+    }
+}
 
 pub fn solve_interplanetaries_for_departure_step(state_keeper: &ResMut<StateKeeper>, departure_step: u32, max_look_ahead: u32) -> Vec<(u32,Interplanetary)> {
     let d: usize = 432;
@@ -148,4 +393,245 @@ pub fn solve_interplanetaries_for_departure_step(state_keeper: &ResMut<StateKeep
     }
 
     interplanetaries
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use bevy_math::DVec3;
+    use crate::{BodyState, StateKeeper};
+    use super::{interplanetary, ArrivalMode, HyperbolaFamily, LaunchSite, OrbitGeometry};
+
+    const MU_EARTH: f64 = 3.986004418e14;
+    const RP: f64 = 6378.137e3 + 180e3;
+    const MU_MARS: f64 = 42828.375214e9;
+    const R_MARS: f64 = 3396.19e3;
+
+    // Speed at periapsis of a hyperbola, from the vis-viva equation
+    fn hyperbola_periapsis_speed(mu: f64, rp: f64, v_inf: f64) -> f64 {
+        (v_inf * v_inf + 2.0 * mu / rp).sqrt()
+    }
+
+    // Outgoing and incoming asymptote directions of a hyperbola, recovered from its periapsis state
+    fn asymptotes(mu: f64, state: &BodyState) -> (DVec3, DVec3) {
+        let [r, v] = *state;
+        let h = r.cross(v);
+        let e_vec = v.cross(h) / mu - r.normalize();
+        let p = e_vec.normalize();
+        let q = h.normalize().cross(p);
+        let f_inf = (-1.0 / e_vec.length()).acos();
+        (p * f_inf.cos() + q * f_inf.sin(), -p * f_inf.cos() + q * f_inf.sin())
+    }
+
+    fn assert_hyperbola(family: &HyperbolaFamily, θ: f64) {
+        let state = family.periapsis_state(θ);
+        let [r, v] = state;
+        assert!((r.length() - family.rp).abs() < 1e-3);
+        assert!(r.normalize().dot(v.normalize()).abs() < 1e-12);
+        let energy = 0.5 * v.length_squared() - family.mu / r.length();
+        assert!((energy - 0.5 * family.v_inf.length_squared()).abs() < 1e-6 * energy.abs());
+        assert!(r.cross(v).normalize().abs_diff_eq(family.plane_normal(θ), 1e-12));
+
+        let (outgoing, incoming) = asymptotes(family.mu, &state);
+        let asymptote = if family.departure { outgoing } else { incoming };
+        assert!(asymptote.abs_diff_eq(family.v_inf.normalize(), 1e-9), "{} vs {}", asymptote, family.v_inf.normalize());
+    }
+
+    #[test]
+    fn equatorial_v_inf() {
+        let v_inf = DVec3::new(2000.0, 2500.0, 0.0);
+        for departure in [true, false] {
+            let family = HyperbolaFamily::new(MU_EARTH, RP, v_inf, departure, DVec3::Z);
+            assert!(family.declination().abs() < 1e-12);
+
+            let angles = family.angles_for_inclination(0.0);
+            assert_eq!(angles.len(), 1);
+            assert!(family.plane_normal(angles[0]).abs_diff_eq(DVec3::Z, 1e-12));
+
+            for θ in angles.into_iter().chain(family.angles_for_inclination(28.5f64.to_radians())) {
+                assert_hyperbola(&family, θ);
+            }
+        }
+    }
+
+    #[test]
+    fn polar_v_inf() {
+        let v_inf = DVec3::new(0.0, 0.0, -3000.0);
+        for departure in [true, false] {
+            let family = HyperbolaFamily::new(MU_EARTH, RP, v_inf, departure, DVec3::Z);
+            assert!((family.declination() + PI / 2.0).abs() < 1e-12);
+
+            assert!(family.angles_for_inclination(0.0).is_empty());
+            assert!(family.angles_for_inclination(60f64.to_radians()).is_empty());
+            assert_eq!(family.angles_for_inclination(PI / 2.0).len(), 1);
+
+            for θ in (0..12).map(|k| k as f64 * PI / 6.0) {
+                assert!(family.plane_normal(θ).dot(DVec3::Z).abs() < 1e-12);
+                assert_hyperbola(&family, θ);
+            }
+        }
+    }
+
+    #[test]
+    fn inclined_v_inf_matches_requested_inclination() {
+        let v_inf = DVec3::new(1500.0, -2200.0, 1800.0);
+        let pole = DVec3::new(0.0, -0.395, 0.918);
+        for departure in [true, false] {
+            let family = HyperbolaFamily::new(MU_EARTH, RP, v_inf, departure, pole);
+            let δ = family.declination();
+
+            assert!(family.angles_for_inclination(δ.abs() * 0.9).is_empty());
+            assert!((family.plane_normal(0.0).dot(pole.normalize()).acos() - δ.abs()).abs() < 1e-9);
+
+            let i = δ.abs() + 0.2;
+            let angles = family.angles_for_inclination(i);
+            assert_eq!(angles.len(), 2);
+            for θ in angles {
+                assert!((family.plane_normal(θ).dot(pole.normalize()).acos() - i).abs() < 1e-9);
+                assert_hyperbola(&family, θ);
+            }
+        }
+    }
+
+    // 180 km circular parking orbit around Earth, leaving with a 3 km/s hyperbolic excess
+    #[test]
+    fn parking_orbit_departure_dv() {
+        let orbit = OrbitGeometry::circular(180e3, 0.0);
+        let v_park = orbit.periapsis_speed(MU_EARTH, RP - orbit.periapsis_altitude);
+        let vp = hyperbola_periapsis_speed(MU_EARTH, RP, 3000.0);
+        assert!((v_park - 7796.122).abs() < 1e-3, "{}", v_park);
+        assert!((vp - 11426.244).abs() < 1e-3, "{}", vp);
+        assert!((vp - v_park - 3630.121).abs() < 1e-3, "{}", vp - v_park);
+    }
+
+    // Arriving at Mars with a 2.65 km/s hyperbolic excess on a 180 km periapsis, where the hyperbola's speed is
+    // 5565.470 m/s against 3460.633 m/s circular and 4894.074 m/s escape
+    #[test]
+    fn arrival_mode_capture_dv() {
+        let vp = hyperbola_periapsis_speed(MU_MARS, R_MARS + 180e3, 2650.0);
+        assert!((vp - 5565.470).abs() < 1e-3, "{}", vp);
+
+        let circular = ArrivalMode::Orbit(OrbitGeometry::circular(180e3, 0.0)).capture_dv(MU_MARS, R_MARS, vp);
+        assert!((circular - 2104.837).abs() < 1e-3, "{}", circular);
+        let capture_only = ArrivalMode::CaptureOnly { periapsis_altitude: 180e3 }.capture_dv(MU_MARS, R_MARS, vp);
+        assert!((capture_only - 671.396).abs() < 1e-3, "{}", capture_only);
+        // Stopping at 180 x 17000 km keeps most of the hyperbola's energy
+        let elliptic = ArrivalMode::Orbit(OrbitGeometry::from_apsides(180e3, 17000e3, R_MARS, 0.0)).capture_dv(MU_MARS, R_MARS, vp);
+        assert!((elliptic - 1051.180).abs() < 1e-3, "{}", elliptic);
+        assert!(capture_only < elliptic && elliptic < circular);
+
+        // Leaving the atmosphere on a 100 x 180 km orbit, only the burn at apoapsis raising periapsis to 180 km is
+        // left, whatever the arrival speed
+        let aerocapture = ArrivalMode::Aerocapture { orbit: OrbitGeometry::circular(180e3, 0.0), entry_altitude: 100e3 };
+        for speed in [vp, 2.0 * vp] {
+            let dv = aerocapture.capture_dv(MU_MARS, R_MARS, speed);
+            assert!((dv - 19.628).abs() < 1e-3, "{}", dv);
+        }
+        let no_burn = ArrivalMode::Aerocapture { orbit: OrbitGeometry::circular(180e3, 0.0), entry_altitude: 180e3 };
+        assert!(no_burn.capture_dv(MU_MARS, R_MARS, vp).abs() < 1e-9);
+    }
+
+    // The Δv of a real transfer, from a polar parking orbit (so there's never a plane change) into each arrival mode,
+    // matches the periapsis speeds of hyperbolas with its excess speeds
+    #[test]
+    fn transfer_dv_by_arrival_mode() {
+        let mut app = crate::tests::headless_app_with(crate::tests::transfer_scenario());
+        app.update();
+        let mut state_keeper = app.world_mut().resource_mut::<StateKeeper>();
+        state_keeper.departure_orbit = OrbitGeometry::circular(180e3, PI / 2.0);
+        let (departure, arrival) = (state_keeper.interplanetary_selection.0, state_keeper.interplanetary_selection.1);
+        let (departure_step, arrival_step) = crate::tests::TRANSFER_STEPS;
+        let body = |id: u32| {
+            let info = state_keeper.info.get(&id).unwrap();
+            (info.mu, info.radius)
+        };
+        let ((mu1, radius1), (mu2, radius2)) = (body(departure), body(arrival));
+        let (rp1, rp2) = (radius1 + 180e3, radius2 + 180e3);
+
+        let modes = [
+            ArrivalMode::Orbit(OrbitGeometry::circular(180e3, 0.0)),
+            ArrivalMode::Orbit(OrbitGeometry::from_apsides(180e3, 17000e3, radius2, 0.0)),
+            ArrivalMode::CaptureOnly { periapsis_altitude: 180e3 },
+            ArrivalMode::Aerocapture { orbit: OrbitGeometry::circular(180e3, 0.0), entry_altitude: 180e3 },
+        ];
+        let mut dv2s = Vec::new();
+        for mode in modes {
+            state_keeper.arrival_mode = mode.clone();
+            let ip = interplanetary(&state_keeper, departure_step, arrival_step, departure, arrival, true);
+            assert!(ip.dla_achievable() && ip.plane_change_dv.abs() < 1e-6, "{}", ip.plane_change_dv);
+            let dv1 = hyperbola_periapsis_speed(mu1, rp1, ip.v_inf1) - (mu1 / rp1).sqrt();
+            assert!((ip.dv1 - dv1).abs() < 1e-3, "{} vs {}", ip.dv1, dv1);
+
+            let vp2 = hyperbola_periapsis_speed(mu2, rp2, ip.v_inf2);
+            let dv2 = match mode {
+                ArrivalMode::Orbit(orbit) => vp2 - orbit.periapsis_speed(mu2, radius2),
+                ArrivalMode::CaptureOnly { .. } => vp2 - (2.0 * mu2 / rp2).sqrt(),
+                ArrivalMode::Aerocapture { .. } => 0.0,
+            };
+            assert!((ip.dv2 - dv2).abs() < 1e-3, "{} vs {}", ip.dv2, dv2);
+            dv2s.push(ip.dv2);
+        }
+        // Circular, elliptic, capture only, aerocapture
+        assert!(dv2s.windows(2).all(|pair| pair[1] < pair[0]), "{:?}", dv2s);
+    }
+
+    // cos i = cos(latitude) sin(azimuth), at whichever allowed azimuth is closest to due east
+    #[test]
+    fn launch_site_minimum_inclination() {
+        let latitude = 28.5f64.to_radians();
+        let site = |azimuth_min: f64, azimuth_max: f64| LaunchSite {
+            latitude, azimuth_min: azimuth_min.to_radians(), azimuth_max: azimuth_max.to_radians(),
+        };
+        let cos_i = |azimuth: f64| latitude.cos() * azimuth.to_radians().sin();
+
+        assert!((site(35.0, 120.0).minimum_inclination() - latitude).abs() < 1e-12);
+        // Allowed only north or only south of east, the limit nearest east (80 or 100 deg) sets it
+        for (azimuth_min, azimuth_max, limit) in [(35.0, 80.0, 80.0), (100.0, 150.0, 100.0)] {
+            let i = site(azimuth_min, azimuth_max).minimum_inclination();
+            assert!((i.cos() - cos_i(limit)).abs() < 1e-12, "{} vs {}", i.cos(), cos_i(limit));
+            assert!(i > latitude);
+        }
+        // Due east from the equator reaches the equator
+        let equator = LaunchSite { latitude: 0.0, ..site(35.0, 120.0) };
+        assert!(equator.minimum_inclination().abs() < 1e-12);
+    }
+
+    // A departure asymptote with |DLA| within the parking inclination needs no plane change. Beyond it the difference
+    // is turned at the injection burn, costing v² = vp² + v_park² - 2 vp v_park cos(plane change).
+    #[test]
+    fn dla_plane_change() {
+        let mut app = crate::tests::headless_app_with(crate::tests::transfer_scenario());
+        app.update();
+        let mut state_keeper = app.world_mut().resource_mut::<StateKeeper>();
+        let (departure, arrival) = (state_keeper.interplanetary_selection.0, state_keeper.interplanetary_selection.1);
+        let (departure_step, arrival_step) = crate::tests::TRANSFER_STEPS;
+        let info = state_keeper.info.get(&departure).unwrap();
+        let (mu, rp) = (info.mu, info.radius + 180e3);
+        state_keeper.launch_site = LaunchSite { latitude: 0.0, azimuth_min: 0.0, azimuth_max: PI };
+
+        let dla = interplanetary(&state_keeper, departure_step, arrival_step, departure, arrival, true).dla;
+        assert!(dla.abs() > 0.1, "{}", dla.to_degrees());
+        for i in [dla.abs() + 0.05, dla.abs(), dla.abs() - 0.1, 0.0] {
+            state_keeper.departure_orbit = OrbitGeometry::circular(180e3, i);
+            let ip = interplanetary(&state_keeper, departure_step, arrival_step, departure, arrival, true);
+            assert!((ip.dla - dla).abs() < 1e-12);
+            assert!((ip.parking_inclination - i).abs() < 1e-12);
+
+            let plane_change = (dla.abs() - i).max(0.0);
+            assert!((ip.plane_change - plane_change).abs() < 1e-12, "{} vs {}", ip.plane_change, plane_change);
+            assert_eq!(ip.dla_achievable(), plane_change == 0.0);
+            let vp = hyperbola_periapsis_speed(mu, rp, ip.v_inf1);
+            let v_park = (mu / rp).sqrt();
+            let dv1 = (vp * vp + v_park * v_park - 2.0 * vp * v_park * plane_change.cos()).sqrt();
+            assert!((ip.dv1 - dv1).abs() < 1e-3, "{} vs {}", ip.dv1, dv1);
+            assert!((ip.plane_change_dv - (dv1 - (vp - v_park))).abs() < 1e-3, "{}", ip.plane_change_dv);
+        }
+
+        // The launch site's latitude raises a lower requested inclination, and so the plane change drops with it
+        state_keeper.departure_orbit = OrbitGeometry::circular(180e3, 0.0);
+        state_keeper.launch_site = LaunchSite { latitude: dla.abs(), azimuth_min: 0.0, azimuth_max: PI };
+        let ip = interplanetary(&state_keeper, departure_step, arrival_step, departure, arrival, true);
+        assert!((ip.parking_inclination - dla.abs()).abs() < 1e-12);
+        assert!(ip.dla_achievable() && ip.plane_change_dv.abs() < 1e-6, "{}", ip.plane_change_dv);
+    }
+}
@@ -1,13 +1,13 @@
 use std::collections::HashMap;
 use std::fmt;
-use bevy::prelude::*;
 use crate::*;
 use bevy::render::mesh::PrimitiveTopology;
 use bevy_math::{DMat3, DVec3};
 use big_space::prelude::*;
+use serde::{Deserialize, Serialize};
 // https://ssd.jpl.nasa.gov/planets/approx_pos.html
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct OE {
     pub a: f64,
     pub e: f64,
@@ -28,6 +28,11 @@ impl OE {
             Ω: 0.0,
         }
     }
+
+    // Semi-latus rectum
+    pub fn p(&self) -> f64 {
+        self.a * (1.0 - self.e * self.e)
+    }
 }
 
 impl fmt::Display for OE {
@@ -68,15 +73,177 @@ pub fn pqw_to_inertial_rot(Ω: f64, ω: f64, i: f64 ) -> DMat3 {
         ),
     )
 }
-pub fn true_anomoly_from_mean_anomaly(e: f64, M: f64) -> f64 {
-    let mut E = M;
-    for _ in 0..10 {
-        let f = E - e * E.sin() - M;
-        let f_prime = 1.0 - e * E.cos();
-        E -= f / f_prime;
+
+// Conics closer to parabolic than this are treated as parabolas
+const PARABOLIC_TOLERANCE: f64 = 1e-9;
+
+pub fn eccentric_anomaly_from_true_anomaly(e: f64, f: f64) -> f64 {
+    2.0 * ((1.0 - e).sqrt() * (f / 2.0).sin()).atan2((1.0 + e).sqrt() * (f / 2.0).cos())
+}
+
+pub fn true_anomaly_from_eccentric_anomaly(e: f64, E: f64) -> f64 {
+    2.0 * ((1.0 + e).sqrt() * (E / 2.0).sin()).atan2((1.0 - e).sqrt() * (E / 2.0).cos())
+}
+
+pub fn hyperbolic_anomaly_from_true_anomaly(e: f64, f: f64) -> f64 {
+    2.0 * (((e - 1.0) / (e + 1.0)).sqrt() * (f / 2.0).tan()).atanh()
+}
+
+pub fn true_anomaly_from_hyperbolic_anomaly(e: f64, H: f64) -> f64 {
+    2.0 * (((e + 1.0) / (e - 1.0)).sqrt() * (H / 2.0).tanh()).atan()
+}
+
+// Mean anomaly for any conic: E - e sin(E) for ellipses, e sinh(H) - H for hyperbolas, and Barker's
+// tan(f/2)/2 + tan^3(f/2)/6 for parabolas
+pub fn mean_anomaly_from_true_anomaly(e: f64, f: f64) -> f64 {
+    if (e - 1.0).abs() < PARABOLIC_TOLERANCE {
+        let D = (f / 2.0).tan();
+        D / 2.0 + D.powi(3) / 6.0
+    } else if e < 1.0 {
+        let E = eccentric_anomaly_from_true_anomaly(e, f);
+        E - e * E.sin()
+    } else {
+        let H = hyperbolic_anomaly_from_true_anomaly(e, f);
+        e * H.sinh() - H
+    }
+}
+
+// Inverse of mean_anomaly_from_true_anomaly. Kepler's equation is solved by Newton's method to a fixed tolerance for
+// ellipses and hyperbolas; Barker's equation is a cubic with a closed form root.
+pub fn true_anomaly_from_mean_anomaly(e: f64, M: f64) -> f64 {
+    if (e - 1.0).abs() < PARABOLIC_TOLERANCE {
+        let y = 3.0 * M;
+        let A = (y + (y * y + 1.0).sqrt()).cbrt();
+        2.0 * (A - 1.0 / A).atan()
+    } else if e < 1.0 {
+        let M = M.rem_euclid(2.0 * PI);
+        let mut E = if e < 0.8 { M } else { PI };
+        for _ in 0..50 {
+            let step = (E - e * E.sin() - M) / (1.0 - e * E.cos());
+            E -= step;
+            if step.abs() < 1e-14 {
+                break;
+            }
+        }
+        true_anomaly_from_eccentric_anomaly(e, E)
+    } else {
+        let mut H = M.signum() * (2.0 * M.abs() / e + 1.8).ln();
+        for _ in 0..100 {
+            let step = (e * H.sinh() - H - M) / (e * H.cosh() - 1.0);
+            H -= step;
+            if step.abs() < 1e-14 * H.abs().max(1.0) {
+                break;
+            }
+        }
+        true_anomaly_from_hyperbolic_anomaly(e, H)
+    }
+}
+
+// Rate of change of the mean anomaly above, given the semi-latus rectum p (finite for every conic, unlike a)
+pub fn mean_motion(mu: f64, p: f64, e: f64) -> f64 {
+    if (e - 1.0).abs() < PARABOLIC_TOLERANCE {
+        (mu / p.powi(3)).sqrt()
+    } else {
+        (mu * (1.0 - e * e).abs().powi(3) / p.powi(3)).sqrt()
+    }
+}
+
+pub fn time_since_periapsis(mu: f64, p: f64, e: f64, f: f64) -> f64 {
+    mean_anomaly_from_true_anomaly(e, f) / mean_motion(mu, p, e)
+}
+
+pub fn true_anomaly_at_time(mu: f64, p: f64, e: f64, t: f64) -> f64 {
+    true_anomaly_from_mean_anomaly(e, mean_motion(mu, p, e) * t)
+}
+
+// Stumpff functions C(z) and S(z), switching to their series near z = 0
+pub fn stumpff_c(z: f64) -> f64 {
+    if z > 1e-6 {
+        (1.0 - z.sqrt().cos()) / z
+    } else if z < -1e-6 {
+        ((-z).sqrt().cosh() - 1.0) / -z
+    } else {
+        1.0 / 2.0 - z / 24.0 + z * z / 720.0
+    }
+}
+
+pub fn stumpff_s(z: f64) -> f64 {
+    if z > 1e-6 {
+        let sz = z.sqrt();
+        (sz - sz.sin()) / sz.powi(3)
+    } else if z < -1e-6 {
+        let sz = (-z).sqrt();
+        (sz.sinh() - sz) / sz.powi(3)
+    } else {
+        1.0 / 6.0 - z / 120.0 + z * z / 5040.0
+    }
+}
+
+// Universal anomaly χ reached after dt from a state with radius r0, radial velocity vr0 and alpha = 1/a (zero for a
+// parabola, negative for a hyperbola), by Newton's method on the universal Kepler equation (Curtis, Algorithm 3.3).
+// Initial guesses follow Vallado.
+pub fn universal_anomaly(mu: f64, r0: f64, vr0: f64, alpha: f64, dt: f64) -> f64 {
+    let sqrt_mu = mu.sqrt();
+    let mut χ = if alpha > 1e-12 {
+        sqrt_mu * dt * alpha
+    } else if alpha < -1e-12 {
+        let a = 1.0 / alpha;
+        let guess = dt.signum() * (-a).sqrt()
+            * ((-2.0 * mu * alpha * dt) / (r0 * vr0 + dt.signum() * (-mu * a).sqrt() * (1.0 - r0 * alpha))).ln();
+        if guess.is_finite() { guess } else { sqrt_mu * dt / r0 }
+    } else {
+        sqrt_mu * dt / r0
+    };
+
+    for _ in 0..100 {
+        let z = alpha * χ * χ;
+        let C = stumpff_c(z);
+        let S = stumpff_s(z);
+        let F = r0 * vr0 / sqrt_mu * χ * χ * C + (1.0 - alpha * r0) * χ.powi(3) * S + r0 * χ - sqrt_mu * dt;
+        let dF = r0 * vr0 / sqrt_mu * χ * (1.0 - z * S) + (1.0 - alpha * r0) * χ * χ * C + r0;
+        let step = F / dF;
+        χ -= step;
+        if step.abs() < 1e-12 * χ.abs().max(1.0) {
+            break;
+        }
     }
+    χ
+}
+
+// Propagate a two-body state by dt with the Lagrange coefficients in terms of the universal anomaly. Valid for
+// elliptic, parabolic and hyperbolic orbits alike, and for negative dt.
+pub fn propagate_rv(mu: f64, body_state: &BodyState, dt: f64) -> BodyState {
+    let [r0, v0] = *body_state;
+    let sqrt_mu = mu.sqrt();
+    let r0_mag = r0.length();
+    let vr0 = r0.dot(v0) / r0_mag;
+    let alpha = 2.0 / r0_mag - v0.length_squared() / mu;
+
+    let χ = universal_anomaly(mu, r0_mag, vr0, alpha, dt);
+    let z = alpha * χ * χ;
+    let C = stumpff_c(z);
+    let S = stumpff_s(z);
 
-    2.0 * (( (1.0 + e).sqrt() * (E / 2.0).sin() ) / ( (1.0 - e).sqrt() * (E / 2.0).cos() )).atan()
+    let f = 1.0 - χ * χ / r0_mag * C;
+    let g = dt - χ.powi(3) / sqrt_mu * S;
+    let r = f * r0 + g * v0;
+    let r_mag = r.length();
+    let f_dot = sqrt_mu / (r_mag * r0_mag) * (alpha * χ.powi(3) * S - χ);
+    let g_dot = 1.0 - χ * χ / r_mag * C;
+    [r, f_dot * r0 + g_dot * v0]
+}
+
+pub fn rv_from_oe(mu: f64, oe: &OE) -> BodyState {
+    let p = oe.p();
+    let r_mag = p / (1.0 + oe.e * oe.f.cos());
+    let r_pqw = DVec3::new(r_mag * oe.f.cos(), r_mag * oe.f.sin(), 0.0);
+    let v_pqw = (mu / p).sqrt() * DVec3::new(-oe.f.sin(), oe.e + oe.f.cos(), 0.0);
+    let rot = pqw_to_inertial_rot(oe.Ω, oe.ω, oe.i);
+    [rot * r_pqw, rot * v_pqw]
+}
+
+pub fn propagate_oe(mu: f64, oe: &OE, dt: f64) -> OE {
+    oe_from_rv(mu, &propagate_rv(mu, &rv_from_oe(mu, oe), dt))
 }
 
 pub fn oe_from_rv(mu: f64, body_state: &BodyState) -> OE {
@@ -151,25 +318,111 @@ pub fn lambert(mu: f64, r1: &DVec3, r2: &DVec3, dt: f64) -> OE {
     oe_from_rv(mu, &[*r1,DVec3::from_array(v1)])
 }
 
-pub fn oe_to_vec(oe: &OE) -> Vec<Vec3> {
-    let mut positions: Vec<Vec3> = Vec::new();
-    if oe.a >= 0.0 { // If elliptical
-        for i in 0..3600 {
-            let theta = (i as f64 / 10.0).to_radians();
-            let r = position_from_true_anomoly(&oe, theta);
-            positions.push(r.as_vec3());
+// Orbit lines are sampled evenly in eccentric (or hyperbolic) anomaly, which already bunches points up around
+// periapsis, then each span is halved until its chord strays from the conic by less than ORBIT_TOLERANCE radians as
+// seen from the focus (about a pixel, with the camera at the parent body). Hyperbolas stop at HYPERBOLA_EXTENT times
+// their periapsis distance.
+const ORBIT_SPANS: usize = 64;
+const ORBIT_TOLERANCE: f64 = 1e-3;
+const ORBIT_MAX_DEPTH: u32 = 12;
+const HYPERBOLA_EXTENT: f64 = 50.0;
+
+// Position relative to the focus at eccentric anomaly E, or hyperbolic anomaly H when e > 1
+fn position_from_anomaly(oe: &OE, anomaly: f64) -> DVec3 {
+    let r_pqw = if oe.e < 1.0 {
+        DVec3::new(oe.a * (anomaly.cos() - oe.e), oe.a * (1.0 - oe.e * oe.e).sqrt() * anomaly.sin(), 0.0)
+    } else {
+        DVec3::new(oe.a * (anomaly.cosh() - oe.e), -oe.a * (oe.e * oe.e - 1.0).sqrt() * anomaly.sinh(), 0.0)
+    };
+    pqw_to_inertial_rot(oe.Ω, oe.ω, oe.i) * r_pqw
+}
+
+// The orbit's line, in f64 and relative to the focus. Ellipses are closed (the last point is the first).
+pub fn orbit_points(oe: &OE) -> Vec<DVec3> {
+    let (from, to) = if oe.e < 1.0 {
+        (0.0, 2.0 * PI)
+    } else {
+        // r = |a| (e cosh H - 1), out to HYPERBOLA_EXTENT periapses
+        let limit = ((HYPERBOLA_EXTENT * (oe.e - 1.0) + 1.0) / oe.e).acosh();
+        (-limit, limit)
+    };
+    let mut points = vec![position_from_anomaly(oe, from)];
+    for i in 0..ORBIT_SPANS {
+        let x_0 = from + (to - from) * i as f64 / ORBIT_SPANS as f64;
+        let x_1 = from + (to - from) * (i + 1) as f64 / ORBIT_SPANS as f64;
+        subdivide(oe, x_0, x_1, *points.last().unwrap(), position_from_anomaly(oe, x_1), 0, &mut points);
+    }
+    points
+}
+
+// Push the points after p_0 up to and including p_1, halving the span while its chord is too far off the conic
+fn subdivide(oe: &OE, x_0: f64, x_1: f64, p_0: DVec3, p_1: DVec3, depth: u32, points: &mut Vec<DVec3>) {
+    let x_mid = (x_0 + x_1) / 2.0;
+    let p_mid = position_from_anomaly(oe, x_mid);
+    if depth < ORBIT_MAX_DEPTH && (p_mid - (p_0 + p_1) / 2.0).length() > ORBIT_TOLERANCE * p_mid.length() {
+        subdivide(oe, x_0, x_mid, p_0, p_mid, depth + 1, points);
+        subdivide(oe, x_mid, x_1, p_mid, p_1, depth + 1, points);
+    } else {
+        points.push(p_1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use bevy_math::DVec3;
+    use crate::BodyState;
+    use super::*;
+
+    const MU_EARTH: f64 = 3.986004418e14;
+
+    fn assert_states_eq(a: &BodyState, b: &BodyState) {
+        assert!(a[0].abs_diff_eq(b[0], 1e-6 * b[0].length()), "{} vs {}", a[0], b[0]);
+        assert!(a[1].abs_diff_eq(b[1], 1e-6 * b[1].length()), "{} vs {}", a[1], b[1]);
+    }
+
+    #[test]
+    fn propagate_there_and_back() {
+        let r = DVec3::new(7000e3, 0.0, 0.0);
+        let escape = (2.0 * MU_EARTH / r.length()).sqrt();
+        let direction = DVec3::new(0.2, 0.9, 0.3).normalize();
+        // The parabola is launched perpendicular to the radius at exactly escape speed
+        let parabolic = [r, DVec3::new(0.0, escape, 0.0)];
+        assert!((oe_from_rv(MU_EARTH, &parabolic).e - 1.0).abs() < 1e-12);
+        for state in [[r, 7.5e3 * direction], parabolic, [r, 12e3 * direction]] {
+            for dt in [600.0, 5000.0, 40000.0] {
+                assert_states_eq(&propagate_rv(MU_EARTH, &propagate_rv(MU_EARTH, &state, dt), -dt), &state);
+            }
         }
-    } else { // If hyperbolic
-        let theta_limit = (-1.0 / oe.e).acos();
-        let steps = 3600;
-        let dtheta = (theta_limit * 2.0) / steps as f64;
-
-        for i in 10..steps-10 {
-            let theta = -theta_limit + dtheta * i as f64;
-            let r = position_from_true_anomoly(&oe, theta);
-            positions.push(r.as_vec3());
+    }
+
+    // Going back in time lands where Kepler's equation says the orbit was
+    #[test]
+    fn propagate_backwards() {
+        let oe = OE { a: 12000e3, e: 0.4, i: 0.5, f: 1.0, ω: 0.3, Ω: 2.0 };
+        let t = time_since_periapsis(MU_EARTH, oe.p(), oe.e, oe.f);
+        for dt in [-600.0, -5000.0, -20000.0] {
+            let expected = OE { f: true_anomaly_at_time(MU_EARTH, oe.p(), oe.e, t + dt), ..oe.clone() };
+            assert_states_eq(&propagate_rv(MU_EARTH, &rv_from_oe(MU_EARTH, &oe), dt), &rv_from_oe(MU_EARTH, &expected));
         }
     }
 
-    positions
-}
\ No newline at end of file
+    // Mean to true anomaly (through the eccentric or hyperbolic anomaly) and back is the identity, for every conic
+    #[test]
+    fn mean_anomaly_round_trip() {
+        for e in [0.0, 0.1, 0.5, 0.9, 0.99] {
+            for n in -12..12 {
+                let mean = n as f64 / 12.0 * PI + 0.1;
+                let back = mean_anomaly_from_true_anomaly(e, true_anomaly_from_mean_anomaly(e, mean));
+                let difference = (back - mean).rem_euclid(2.0 * PI);
+                assert!(difference.min(2.0 * PI - difference) < 1e-10, "e = {}, M = {} came back as {}", e, mean, back);
+            }
+        }
+        for e in [1.0, 1.01, 1.5, 3.0] {
+            for mean in [-50.0, -3.0, -0.2, 0.0, 0.2, 3.0, 50.0] {
+                let back = mean_anomaly_from_true_anomaly(e, true_anomaly_from_mean_anomaly(e, mean));
+                assert!((back - mean).abs() < 1e-9 * mean.abs().max(1.0), "e = {}, M = {} came back as {}", e, mean, back);
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::f64::consts::PI;
+use bevy::prelude::*;
+use bevy::render::mesh::PrimitiveTopology;
+use bevy_math::DVec3;
+use big_space::prelude::*;
+use crate::keplerian::OE;
+use crate::StateKeeper;
+// Line meshes (orbits, trails, transfer legs, ephemerides) are created once per entity and rewritten in place, and
+// only when what they're drawn from changes: the view (inertial body, rotating frame, trails toggle) or the line's own
+// source. In between, only their placement in the grid moves. Conics count as changed once an element drifts past
+// ELEMENT_TOLERANCE, since perturbations nudge the osculating elements a little every step.
+
+pub const ELEMENT_TOLERANCE: f64 = 1e-4; // Relative for a, radians (or plain difference, for e) for the rest
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct View {
+    inertial: u32,
+    synodic: Option<(u32, u32)>,
+    trails: bool,
+}
+
+impl View {
+    pub fn of(state_keeper: &StateKeeper) -> Self {
+        View { inertial: state_keeper.inertial, synodic: state_keeper.synodic, trails: state_keeper.trails }
+    }
+}
+
+// What a line is drawn from, besides the view
+#[derive(Clone, PartialEq)]
+pub enum LineSource {
+    Empty,
+    Conic([f64; 5]), // a, e, i, ω, Ω
+    Window(u32, u32), // A trail over these steps
+    Transfer(u32, u32, [f64; 3]), // Departure and arrival steps, and the three legs' semi-major axes
+    Fixed, // Depends on the view alone
+}
+
+impl LineSource {
+    pub fn conic(oe: &OE) -> Self {
+        LineSource::Conic([oe.a, oe.e, oe.i, oe.ω, oe.Ω])
+    }
+
+    fn matches(&self, other: &LineSource) -> bool {
+        match (self, other) {
+            (LineSource::Conic(a), LineSource::Conic(b)) => {
+                ((a[0] - b[0]) / b[0]).abs() < ELEMENT_TOLERANCE
+                    && a[1..].iter().zip(&b[1..]).all(|(x, y)| ((x - y + PI).rem_euclid(2.0 * PI) - PI).abs() < ELEMENT_TOLERANCE)
+            }
+            _ => self == other,
+        }
+    }
+}
+
+// What a line's mesh was last built from, and where its first point is. Vertices are stored in f32 relative to that
+// anchor, which is itself relative to an origin each display system works out per frame (the parent body for conics,
+// the inertial body for trails), so moving the line never needs its vertices.
+#[derive(Component)]
+pub struct LineCache {
+    view: Option<View>,
+    source: LineSource,
+    pub anchor: DVec3,
+}
+
+impl Default for LineCache {
+    fn default() -> Self {
+        LineCache { view: None, source: LineSource::Empty, anchor: DVec3::ZERO }
+    }
+}
+
+impl LineCache {
+    // Whether the mesh has to be rebuilt for this view and source, remembering them if so
+    pub fn stale(&mut self, view: View, source: LineSource) -> bool {
+        if self.view == Some(view) && self.source.matches(&source) {
+            return false;
+        }
+        self.view = Some(view);
+        self.source = source;
+        true
+    }
+
+    // Rewrite the mesh's vertices in place, and anchor the line at its first point
+    pub fn rebuild(&mut self, meshes: &mut Assets<Mesh>, mesh: &Handle<Mesh>, points: &[DVec3]) {
+        self.anchor = points.first().copied().unwrap_or(DVec3::ZERO);
+        if let Some(mesh) = meshes.get_mut(mesh) {
+            let positions: Vec<Vec3> = points.iter().map(|p| (*p - self.anchor).as_vec3()).collect();
+            mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        }
+    }
+
+    // Put the line's anchor at origin + anchor in the grid
+    pub fn place(&self, root_grid: &Grid<i64>, origin: DVec3, grid_cell: &mut GridCell<i64>, transform: &mut Transform) {
+        let (new_grid_cell, new_translation) = root_grid.translation_to_grid(origin + self.anchor);
+        *grid_cell = new_grid_cell;
+        transform.translation = new_translation;
+    }
+}
+
+// An empty line strip, for line entities to start out with
+pub fn empty_line() -> Mesh {
+    let mut mesh = Mesh::new(PrimitiveTopology::LineStrip, Default::default());
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, Vec::<Vec3>::new());
+    mesh
+}
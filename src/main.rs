@@ -5,10 +5,26 @@ mod keplerian;
 mod ui;
 mod interplanetary;
 mod porkchop;
+mod equinoctial;
+mod element_history;
+mod timeseries;
+mod frames;
+mod synodic;
+mod cr3bp;
+mod groundtrack;
+mod time;
+mod scenario;
+mod snapshot;
+mod ccsds;
+mod trails;
+mod transfer;
+mod lines;
+mod picking;
+mod spheres;
 
 use std::time::Instant;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::f64::consts::PI;
 use std::f64::INFINITY;
 use std::os::linux::raw::stat;
@@ -16,18 +32,21 @@ use bevy::core_pipeline::bloom::Bloom;
 use bevy::core_pipeline::Skybox;
 use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
 use bevy::image::ImageSampler;
+use bevy::input::InputSystem;
 use bevy::pbr::NotShadowCaster;
 use bevy::prelude::*;
 use bevy::render::camera::Exposure;
-use bevy::render::mesh::PrimitiveTopology;
 use bevy::render::render_resource::{AddressMode, Extent3d, FilterMode, SamplerDescriptor, TextureDimension, TextureFormat};
-use bevy_math::DVec3;
+use bevy_math::{DMat3, DVec3};
 use big_space::prelude::*;
 use crate::camera::CameraState;
 use crate::keplerian::*;
 use crate::ui::*;
 use crate::interplanetary::*;
 use crate::porkchop::*;
+use crate::scenario::Scenario;
+use crate::lines::{LineCache, LineSource};
+use crate::time::{step_to_date, Epoch};
 
 type BodyState = [DVec3;2]; // r, v
 fn add_body_state(a: &BodyState, b: &BodyState) -> BodyState {
@@ -37,6 +56,33 @@ fn add_body_state(a: &BodyState, b: &BodyState) -> BodyState {
 fn sub_body_state(a: &BodyState, b: &BodyState) -> BodyState {
     [a[0] - b[0], a[1] - b[1]]
 }
+// Where a point is drawn at a step: its ICRF position, or its position in the rotating frame when the synodic display
+// is on
+fn display_position(state_keeper: &StateKeeper, step: u32, position: DVec3) -> DVec3 {
+    match state_keeper.synodic {
+        Some((primary, secondary)) => frames::Frame::Synodic(primary, secondary).transform_from_icrf(state_keeper, step, &[position, DVec3::ZERO])[0],
+        None => position,
+    }
+}
+
+// Rotation from ICRF axes to the axes things are drawn in at the current step
+fn display_rotation(state_keeper: &StateKeeper) -> DMat3 {
+    match state_keeper.synodic {
+        Some((primary, secondary)) => frames::Frame::Synodic(primary, secondary).at(state_keeper, state_keeper.current_step).rotation.transpose(),
+        None => DMat3::IDENTITY,
+    }
+}
+
+// Whether a body (and its orbit and label) is shown: the inertial body and its children normally, or the synodic
+// pair and their children
+fn body_shown(state_keeper: &StateKeeper, id: u32) -> bool {
+    let parent_id = state_keeper.info.get(&id).unwrap().kepler_parent;
+    match state_keeper.synodic {
+        Some((primary, secondary)) => id == primary || id == secondary || (parent_id != id && (parent_id == primary || parent_id == secondary)),
+        None => id == state_keeper.inertial || parent_id == state_keeper.inertial,
+    }
+}
+
 type BodyStates = HashMap<u32, BodyState>; // BodyID -> BodyStates
 type TimeStates = HashMap<u32, BodyStates>; // Time -> BodyIDs
 type BodyInfos = HashMap<u32, BodyInfo>;
@@ -48,6 +94,7 @@ struct BodyInfo {
     radius: f64,
     j2: f64,
     rotational_rate: f64,
+    prime_meridian: f64, // IAU W0, the prime meridian's angle from the equator's ICRF node at J2000
     tilt: DVec3,
     affected: bool,
     affects: bool,
@@ -71,10 +118,20 @@ struct StateKeeper {
     state: TimeStates,
     info: BodyInfos,
     inertial: u32,
-    hypothetical: Option<Entity>,
     interplanetary: Option<Interplanetary>,
     interplanetary_selection: (u32,u32,u32,u32,bool),
-    interplanetaries: HashMap<u32, Vec<(u32, Interplanetary)>>
+    interplanetaries: HashMap<u32, Vec<(u32, Interplanetary)>>,
+    departure_orbit: OrbitGeometry,
+    arrival_mode: ArrivalMode,
+    launch_site: LaunchSite,
+    synodic: Option<(u32, u32)>, // (primary, secondary) whose rotating frame the scene is drawn in, if any
+    epoch: Epoch, // Of step 0
+    scenario: Scenario,
+    ephemerides: Vec<(u32, ccsds::OemSegment)>, // Loaded OEM segments, with the body each is centered on
+    trails: bool, // Draw every body's propagated trail, not just those without a conic
+    selected: Option<u32>, // Body picked with the mouse, whose info panel is open
+    porkchop: Porkchop, // The transfer search's grid, for the porkchop plot
+    spheres: HashSet<u32>, // Bodies whose sphere of influence and Hill sphere are drawn
 }
 
 #[derive(Component)]
@@ -93,9 +150,6 @@ struct ObjectID {
 #[derive(Component)]
 struct OrbitDisplay {}
 
-#[derive(Component)]
-struct Hypothetical {}
-
 #[derive(Component)]
 struct BodyDisplay {}
 
@@ -109,7 +163,16 @@ struct BodyOverlayDisplay {
 struct BodyDisplayGrid {}
 
 fn main() {
+    // A scenario that can't be used is reported, not panicked over (logging isn't up yet, so straight to stderr)
+    let scenario = match Scenario::from_args().and_then(|scenario| scenario.validate(&bodies_init::planets_info()).map(|_| scenario)) {
+        Ok(scenario) => scenario,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
     App::new()
+        .insert_resource(scenario)
         .add_plugins((
             DefaultPlugins,
             BigSpacePlugin::<i64>::default(),
@@ -119,24 +182,52 @@ fn main() {
         .add_systems(Startup, setup)
         .add_systems(Startup, populate_state.after(setup))
         .add_systems(Startup, ui::setup_ui.after(populate_state))
+        .add_systems(Startup, synodic::spawn_lagrange_markers.after(setup))
+        .add_systems(Startup, ccsds::spawn_ephemeris_displays.after(setup))
+        .add_systems(Startup, transfer::spawn_transfer_displays.after(setup))
+        .add_systems(Startup, picking::spawn_info_panel)
+        .add_systems(Startup, spheres::spawn_sphere_displays.after(setup))
         .add_systems(Update, display_state.after(main_tick))
         .add_systems(Update, camera::camera_controller.after(display_state))
+        .add_systems(Update, rotate_bodies.after(display_state))
         .add_systems(Update, main_tick)
         .add_systems(Update, button_interaction)
+        .add_systems(Update, ui::display_browser)
+        .add_systems(PreUpdate, ui::search_input.after(InputSystem))
+        .add_systems(Update, element_history::export_focused_elements)
+        .add_systems(Update, timeseries::plot_transfer_bodies)
+        .add_systems(Update, synodic::toggle_synodic.before(display_state))
+        .add_systems(Update, synodic::display_lagrange_points.after(camera::camera_controller))
+        .add_systems(Update, transfer::display_transfer.after(camera::camera_controller))
+        .add_systems(Update, cr3bp::cycle_cr3bp_orbits.after(synodic::toggle_synodic))
+        .add_systems(Update, groundtrack::show_focused_ground_track)
+        .add_systems(Update, ccsds::display_ephemerides.after(display_state))
+        .add_systems(Update, ccsds::export_ccsds)
+        .add_systems(Update, trails::toggle_trails.before(display_state))
+        .add_systems(Update, picking::pick_bodies.after(camera::camera_controller))
+        .add_systems(Update, picking::display_selection.after(picking::pick_bodies))
+        .add_systems(Update, spheres::toggle_spheres.before(spheres::display_spheres))
+        .add_systems(Update, spheres::display_spheres.after(display_state))
+        .add_systems(Update, spheres::log_soi_crossings)
         .run();
 }
 
 
 // Spawn a StateKeeper, add in every planet/moon with their initial states for T0 (April 1, 2025) in HCI
-fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, mut materials: ResMut<Assets<StandardMaterial>>, asset_server: Res<AssetServer>, mut images: ResMut<Assets<Image>>) {
+fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, mut materials: ResMut<Assets<StandardMaterial>>, asset_server: Res<AssetServer>, mut images: ResMut<Assets<Image>>, scenario: Res<Scenario>) {
     let mut body_states: BodyStates = HashMap::new();
     let mut body_infos: BodyInfos = HashMap::new();
     let mut time_states: TimeStates = HashMap::new();
 
+    let catalog = bodies_init::planets_info();
+    let scenario = scenario.clone(); // Validated in main(), so its names are all in the catalog
+    let focus = scenario.body_id(&catalog, &scenario.focus).unwrap();
+    let departure = scenario.body_id(&catalog, &scenario.transfer.departure).unwrap();
+    let arrival = scenario.body_id(&catalog, &scenario.transfer.arrival).unwrap();
 
     let mut id_count = 0;
     commands.spawn((
-        Text::new("AE313 Space Mechanics Final Project\nWASD to pan/tilt"),
+        Text::new("AE313 Space Mechanics Final Project\nWASD to pan/tilt, F to change frame, R for rotating frame, H for CR3BP orbits, G for ground track, O for CCSDS export, P for element plots, T for trails,\nclick a body to select it, double-click to focus, right-drag to orbit, C for free-fly (WASD, space, shift) and chase, I for SOI/Hill spheres"),
         Node {
             position_type: PositionType::Absolute,
             top: Val::Px(12.0),
@@ -176,8 +267,6 @@ fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, mut materials
            }),
     );
 
-    let mut hypothetical_display: Option<Entity> = None;
-
     commands.spawn_big_space_default(|root_grid: &mut GridCommands<i64>| {
         root_grid.insert(RootGrid {});
         root_grid.with_grid_default(|camera_grid: &mut GridCommands<i64> | {
@@ -197,7 +286,12 @@ fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, mut materials
                     pan: 0.0,
                     tilt: 0.0,
                     dist: 1.5e9,
-                    focused: 0,
+                    focused: focus,
+                    frame: frames::Frame::BodyInertial(focus),
+                    mode: camera::CameraMode::Orbit,
+                    position: DVec3::ZERO,
+                    flight: None,
+                    rotation: Quat::IDENTITY,
                 },
                 Exposure::SUNLIGHT,
                 Bloom::NATURAL,
@@ -208,30 +302,18 @@ fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, mut materials
                 }));
         });
 
-        // Spawn the 'display' orbit for showing transfers, etc etc. only need one i think ??
-        let mut mesh = Mesh::new(PrimitiveTopology::LineStrip, Default::default());
-        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, Vec::<Vec3>::new());
-        hypothetical_display = Some(root_grid.spawn_spatial((
-            Mesh3d(meshes.add(mesh)),
-            MeshMaterial3d(materials.add(StandardMaterial {
-                base_color: Color::rgb(0.5, 0.0, 0.5),
-                emissive: LinearRgba::new(1., 1.0, 1., 0.7),
-                unlit: true,
-                ..default()
-            })),
-            OrbitDisplay {},
-            Hypothetical {},
-        )).id());
-
         // Spawn orbit displays, grids, and spheres for each body to display (planets, moons, etc)
-        for body in bodies_init::planets_info() {
+        for body in catalog {
+            // Left out bodies keep their catalog ID free, so IDs (and kepler_parent) mean the same in every scenario
+            if !scenario.includes(&body.1.name) {
+                id_count += 1;
+                continue;
+            }
             body_states.insert(id_count, body.0);
             body_infos.insert(id_count, body.1);
 
-            let mut mesh = Mesh::new(PrimitiveTopology::LineStrip, Default::default());
-            mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, Vec::<Vec3>::new());
             let orbit_display_id = root_grid.spawn_spatial((
-                Mesh3d(meshes.add(mesh)),
+                Mesh3d(meshes.add(lines::empty_line())),
                 MeshMaterial3d(materials.add(StandardMaterial {
                     base_color: Color::WHITE,
                     emissive: LinearRgba::new(0., 0.5, 0., 0.5),
@@ -239,7 +321,8 @@ fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, mut materials
                     ..default()
                 })),
                 OrbitDisplay {},
-                ObjectID { id: id_count }
+                ObjectID { id: id_count },
+                LineCache::default(),
             )).id();
             body_infos.get_mut(&id_count).unwrap().orbit_display_id = Some(orbit_display_id);
 
@@ -287,7 +370,7 @@ fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, mut materials
         }
     });
 
-    for (_body_id, body_info) in body_infos.iter_mut() {
+    for (body_id, body_info) in body_infos.iter_mut() {
         let body_overlay_display_id = commands.spawn((
             Node {
                 position_type: PositionType::Absolute,
@@ -295,7 +378,7 @@ fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, mut materials
             },
             Text(body_info.name.clone()),
             BodyOverlayDisplay {selected: false, focused: false},
-            ObjectID { id: id_count },
+            ObjectID { id: *body_id },
         )).id();
 
         body_info.body_overlay_display_id = Some(body_overlay_display_id);
@@ -303,42 +386,87 @@ fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, mut materials
 
     time_states.insert(0, body_states);
 
-    commands.insert_resource(StateKeeper {paused: true, current_step: 0, time: 0.0, dt: 100.0, step_limit: 864*365*4, last_step_computed: 0, state: time_states, info: body_infos, inertial: 0, hypothetical: hypothetical_display, interplanetary: None, interplanetary_selection: (3,4,0,864*120,true), interplanetaries: HashMap::new() });
+    let transfer = &scenario.transfer;
+    let first_departure = (transfer.departure_window_days.0 * scenario.steps_per_day() as f64) as u32;
+    let first_arrival = first_departure + (transfer.flight_time_days.0 * scenario.steps_per_day() as f64) as u32;
+    let mut state_keeper = StateKeeper {paused: true, current_step: 0, time: 0.0, dt: scenario.dt, step_limit: scenario.step_limit(), last_step_computed: 0, state: time_states, info: body_infos, inertial: focus, interplanetary: None, interplanetary_selection: (departure, arrival, first_departure, first_arrival, true), interplanetaries: HashMap::new(), departure_orbit: transfer.departure_orbit.clone(), arrival_mode: transfer.arrival_mode.clone(), launch_site: transfer.launch_site.clone(), synodic: None, epoch: Epoch::parse(&scenario.epoch).unwrap(), scenario, ephemerides: Vec::new(), trails: false, selected: None, spheres: HashSet::new(), porkchop: Porkchop::default() };
+    state_keeper.ephemerides = ccsds::load_ephemerides(&state_keeper, &state_keeper.scenario.ephemerides);
+    commands.insert_resource(state_keeper);
 }
 
-fn populate_state(mut state_keeper: ResMut<StateKeeper>) {
-    // Populate the state
+// Populate the state over the whole span
+fn propagate(state_keeper: &mut StateKeeper) {
     for i in 1..state_keeper.step_limit {
         let last_state = state_keeper.state.get(&(i - 1)).unwrap();
-        let new_state = nbody::rk4_step(&state_keeper.info, &last_state, state_keeper.dt);
+        let new_state = state_keeper.scenario.integrator.step(&state_keeper.info, &last_state, state_keeper.dt);
         state_keeper.state.insert(i, new_state);
         state_keeper.last_step_computed=i;
     }
+}
+
+fn populate_state(mut state_keeper: ResMut<StateKeeper>) {
+    // A run with the same inputs may already be cached
+    let key = snapshot::key(&state_keeper);
+    match snapshot::load(&mut state_keeper, key) {
+        Ok(()) => info!("Loaded cached run {}", snapshot::path(key)),
+        Err(e) => {
+            info!("No cached run at {} ({}), propagating", snapshot::path(key), e);
+            propagate(&mut state_keeper);
+            search_transfers(&mut state_keeper);
+            if let Err(e) = snapshot::save(&state_keeper, key) {
+                error!("Couldn't cache run {}: {}", snapshot::path(key), e);
+            }
+        }
+    }
+
+    // Redrawn from the run every time, since another scenario may have written over them since it was cached
+    let porkchop = &state_keeper.porkchop;
+    let height = porkchop.dv_grid.len() as u32;
+    let width  = porkchop.dv_grid.first().map_or(0, |r| r.len()) as u32;
+    if let Err(e) = make_porkchop_plot(&porkchop.dv_grid, &porkchop.dla_grid, width, height, step_to_date(&state_keeper, porkchop.departure_start)) {
+        error!("Couldn't plot porkchop.png: {}", e);
+    }
+}
 
-    // Porkchop plot shenanigans
+// Porkchop plot shenanigans: search the departure window for the lowest Δv transfer, and select it
+fn search_transfers(state_keeper: &mut StateKeeper) {
     let mut dv_grid: Vec<Vec<f32>> = Vec::with_capacity(365 * 2);
-    let step_day = 864;
-    let max_travel = 12 * 30 * step_day;
-    let depart_end = (state_keeper.step_limit - max_travel)
-        .min(2 * 365 * step_day);
+    let mut dla_grid: Vec<Vec<bool>> = Vec::with_capacity(365 * 2);
+    let transfer = state_keeper.scenario.transfer.clone();
+    let (body0, body1) = (state_keeper.interplanetary_selection.0, state_keeper.interplanetary_selection.1);
+    let step_day = state_keeper.scenario.steps_per_day();
+    let min_travel = (transfer.flight_time_days.0 * step_day as f64) as u32;
+    let (window_start, window_end) = state_keeper.scenario.departure_window_days();
+    let depart_start = (window_start * step_day as f64) as u32;
+    // When even the earliest departure can't fly the longest transfer within the span, flights are cut short instead
+    // (validate() leaves room for at least the shortest)
+    let max_travel = ((transfer.flight_time_days.1 * step_day as f64) as u32).min(state_keeper.step_limit - 1 - depart_start);
+    let depart_end = ((window_end * step_day as f64) as u32)
+        .min(state_keeper.step_limit - max_travel)
+        .max(depart_start + 1);
 
     let mut lowest_dv = f64::INFINITY;
     let mut lowest_dv_pos: [u32;2] = [0,0];
     let mut lowest_dv_short: bool = true;
-    for travel in (30*3*step_day .. 30*12*step_day).step_by(step_day as usize) {
+    for travel in (min_travel..max_travel).step_by(step_day as usize) {
         let mut row = Vec::with_capacity(depart_end as usize / step_day as usize);
-        for depart in (0..depart_end).step_by(step_day as usize) {
-            let ip1 = interplanetary(&state_keeper, depart, depart + travel, 3, 4, true);
-            let ip2 = interplanetary(&state_keeper, depart, depart + travel, 3, 4, false);
+        let mut dla_row = Vec::with_capacity(depart_end as usize / step_day as usize);
+        for depart in (depart_start..depart_end).step_by(step_day as usize) {
+            let ip1 = interplanetary(&state_keeper, depart, depart + travel, body0, body1, true);
+            let ip2 = interplanetary(&state_keeper, depart, depart + travel, body0, body1, false);
             let mut is_ip1_lowest = true;
             let mut dv = f64::INFINITY;
+            let dla_achievable;
             if ip1.dv1 + ip1.dv2 < ip2.dv1 + ip2.dv2 {
                 dv = ip1.dv1 + ip1.dv2;
+                dla_achievable = ip1.dla_achievable();
             } else {
                 dv = ip2.dv1 + ip2.dv2;
+                dla_achievable = ip2.dla_achievable();
                 is_ip1_lowest = false;
             }
             row.push(dv as f32);
+            dla_row.push(dla_achievable);
 
             if dv < lowest_dv {
                 lowest_dv = dv;
@@ -348,6 +476,7 @@ fn populate_state(mut state_keeper: ResMut<StateKeeper>) {
             }
         }
         dv_grid.push(row);
+        dla_grid.push(dla_row);
     }
 
     state_keeper.interplanetary_selection.2 = lowest_dv_pos[0];
@@ -355,22 +484,20 @@ fn populate_state(mut state_keeper: ResMut<StateKeeper>) {
     state_keeper.interplanetary_selection.4 = lowest_dv_short;
     state_keeper.interplanetary = Some(interplanetary(&state_keeper, state_keeper.interplanetary_selection.2, state_keeper.interplanetary_selection.3, state_keeper.interplanetary_selection.0, state_keeper.interplanetary_selection.1, state_keeper.interplanetary_selection.4));
 
-    let height = dv_grid.len() as u32;
-    let width  = dv_grid.get(0).map_or(0, |r| r.len()) as u32;
-    make_porkchop_plot(&dv_grid, width, height).unwrap();
+    state_keeper.porkchop = Porkchop { dv_grid, dla_grid, departure_start: depart_start };
 }
 
 fn display_state(
     mut meshes: ResMut<Assets<Mesh>>,
     mut state_keeper: ResMut<StateKeeper>,
-    mut orbit_display_query: Query<(&mut Mesh3d, &mut GridCell<i64>, &mut Transform), (With<OrbitDisplay>, Without<BodyDisplayGrid>, Without<Hypothetical>)>,
+    mut orbit_display_query: Query<(&Mesh3d, &mut GridCell<i64>, &mut Transform, &mut LineCache), (With<OrbitDisplay>, Without<BodyDisplayGrid>)>,
     mut body_display_query: Query<(&mut GridCell<i64>, &mut Transform), (With<BodyDisplayGrid>, Without<OrbitDisplay>)>,
     mut root_grid: Single<&mut Grid<i64>, With<RootGrid>>,
-    mut hypothetical_query: Single<(&mut Mesh3d, &mut GridCell<i64>, &mut Transform), (With<OrbitDisplay>, With<Hypothetical>)>,
     camera: Single<(&Camera, &GlobalTransform, &mut CameraState)>,
 ) {
     let (camera, camera_global_transform, mut camera_state) = camera.into_inner();
     let start = Instant::now();
+    let view = lines::View::of(&state_keeper);
     // Collect the keys to avoid holding an immutable borrow of state_keeper.state
     let body_ids: Vec<_> = state_keeper.state.get(&state_keeper.current_step)
         .unwrap()
@@ -378,64 +505,6 @@ fn display_state(
         .cloned()
         .collect();
 
-    // Display the interplanetary "hypothetical" orbit, if it exists
-    let (mut hypothetical_mesh3d, mut hypothetical_gridcell, mut hypothetical_transform) = hypothetical_query.into_inner();
-    if let Some(f) = &state_keeper.interplanetary {
-        let mut oe = &f.oe0;
-        let mut show = false;
-        let mut id = 0;
-        if state_keeper.inertial == f.body0 {
-            oe = &f.oe0;
-            show = true;
-            id = f.body0;
-        } else if state_keeper.inertial == f.body1 {
-            oe = &f.oe1;
-            show = true;
-            id = f.body1;
-        } else if state_keeper.inertial == f.body2 {
-            oe = &f.oe2;
-            show = true;
-            id = f.body2;
-        }
-
-        if show {
-            let mut mesh = Mesh::new(PrimitiveTopology::LineStrip, Default::default());
-            let mut positions: Vec<Vec3> = oe_to_vec(&oe);
-            let p0= positions[0];
-
-            let (new_grid_cell, new_translation) = root_grid.translation_to_grid(p0.as_dvec3() + state_keeper.state.get(&state_keeper.current_step).unwrap().get(&id).unwrap()[0]);
-            *hypothetical_gridcell = new_grid_cell;
-            hypothetical_transform.translation = new_translation;
-
-            for i in 0..positions.len() {
-                positions[i] = positions[i] - p0;
-            }
-
-            mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
-            hypothetical_mesh3d.0 = meshes.add(mesh);
-        } else {
-            let mut mesh = Mesh::new(PrimitiveTopology::LineStrip, Default::default());
-            let positions: Vec<Vec3> = Vec::new();
-
-            let (new_grid_cell, new_translation) = root_grid.translation_to_grid(DVec3::ZERO);
-            *hypothetical_gridcell = new_grid_cell;
-            hypothetical_transform.translation = new_translation;
-
-            mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
-            hypothetical_mesh3d.0 = meshes.add(mesh);
-        }
-    } else {
-        let mut mesh = Mesh::new(PrimitiveTopology::LineStrip, Default::default());
-        let positions: Vec<Vec3> = Vec::new();
-
-        let (new_grid_cell, new_translation) = root_grid.translation_to_grid(DVec3::ZERO);
-        *hypothetical_gridcell = new_grid_cell;
-        hypothetical_transform.translation = new_translation;
-
-        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
-        hypothetical_mesh3d.0 = meshes.add(mesh);
-    }
-
     // Display the body and orbit of each body at the current step
     for id in body_ids {
         let body_display_grid_id = state_keeper.info.get(&id).unwrap().body_display_grid_id;
@@ -445,41 +514,41 @@ fn display_state(
         if let Some(body_display_grid_id) = body_display_grid_id {
             let (mut body_display_grid_gridcell, mut body_display_grid_transform) = body_display_query.get_mut(body_display_grid_id).unwrap();
             let pos = state_keeper.state.get(&state_keeper.current_step).unwrap().get(&id).unwrap()[0];
-            let (new_grid_cell, new_translation) = root_grid.translation_to_grid(pos);
+            let (new_grid_cell, new_translation) = root_grid.translation_to_grid(display_position(&state_keeper, state_keeper.current_step, pos));
             *body_display_grid_gridcell = new_grid_cell;
             body_display_grid_transform.translation = new_translation;
         }
 
-        // Update the grid the orbit mesh is in to the correct position, and then update the mesh vertices accordingly
+        // Place the orbit line, rebuilding its mesh first if what it shows has changed
         if let Some(orbit_display_id) = orbit_display_id {
             let parent_id = state_keeper.info.get(&id).unwrap().kepler_parent;
             let parent_state = state_keeper.state.get(&state_keeper.current_step).unwrap().get(&parent_id).unwrap();
             let state = state_keeper.state.get(&state_keeper.current_step).unwrap().get(&id).unwrap();
-            let (mut orbit_display_mesh3d, mut orbit_display_gridcell, mut orbit_display_transform) = orbit_display_query.get_mut(orbit_display_id).unwrap();
-
-            if state_keeper.info.get(&id).unwrap().display_as_keplerian { // If we should display the orbit as keplerian, we calculate one full orbit (360deg), and then adjust each position for the origin of the mesh, parent body, and the inertial reference frame
-                if id == state_keeper.inertial || parent_id == state_keeper.inertial {
-                    let oe = oe_from_rv(state_keeper.info.get(&parent_id).unwrap().mu, &sub_body_state(state, parent_state));
-                    let positions: Vec<Vec3> = oe_to_vec(&oe);
-                    let p0= positions[0];
-                    let (new_grid_cell, new_translation) = root_grid.translation_to_grid(p0.as_dvec3() + parent_state[0]);
-                    *orbit_display_gridcell = new_grid_cell;
-                    orbit_display_transform.translation = new_translation;
-
-                    let mut mesh = Mesh::new(PrimitiveTopology::LineStrip, Default::default());
-                    let mut positions: Vec<Vec3> = positions;
-                    for i in 0..positions.len() {
-                        positions[i] = positions[i] - p0;
-                    }
-                    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
-                    orbit_display_mesh3d.0 = meshes.add(mesh);
-                } else {
-                    let mut mesh = Mesh::new(PrimitiveTopology::LineStrip, Default::default());
-                    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, Vec::<Vec3>::new());
-                    orbit_display_mesh3d.0 = meshes.add(mesh);
+            let (orbit_display_mesh3d, mut orbit_display_gridcell, mut orbit_display_transform, mut cache) = orbit_display_query.get_mut(orbit_display_id).unwrap();
+
+            if let Some((primary, secondary)) = state_keeper.synodic { // In the rotating frame, osculating conics mean little, so show each body's path over one revolution of the pair instead
+                let (first, last) = synodic::trail_window(&state_keeper, primary, secondary);
+                let shown = body_shown(&state_keeper, id);
+                if cache.stale(view, if shown { LineSource::Window(first, last) } else { LineSource::Empty }) {
+                    let trail = if shown { synodic::synodic_trail(&state_keeper, primary, secondary, id, first, last, 2000) } else { Vec::new() };
+                    cache.rebuild(&mut meshes, &orbit_display_mesh3d.0, &trail);
                 }
-            } else {
-
+                cache.place(&root_grid, DVec3::ZERO, &mut orbit_display_gridcell, &mut orbit_display_transform);
+            } else if trails::shows_trail(&state_keeper, id) { // Otherwise, bodies without a conic (or all of them, with trails on) get their propagated path around the inertial body
+                let (first, last) = trails::trail_window(&state_keeper);
+                if cache.stale(view, LineSource::Window(first, last)) {
+                    let trail = trails::inertial_trail(&state_keeper, id, first, last, trails::TRAIL_SAMPLES);
+                    cache.rebuild(&mut meshes, &orbit_display_mesh3d.0, &trail);
+                }
+                cache.place(&root_grid, trails::origin(&state_keeper), &mut orbit_display_gridcell, &mut orbit_display_transform);
+            } else if state_keeper.info.get(&id).unwrap().display_as_keplerian && (id == state_keeper.inertial || parent_id == state_keeper.inertial) { // If we should display the orbit as keplerian, we calculate one full orbit (360deg) about the parent, and place it around the parent's current position
+                let oe = oe_from_rv(state_keeper.info.get(&parent_id).unwrap().mu, &sub_body_state(state, parent_state));
+                if cache.stale(view, LineSource::conic(&oe)) {
+                    cache.rebuild(&mut meshes, &orbit_display_mesh3d.0, &orbit_points(&oe));
+                }
+                cache.place(&root_grid, parent_state[0], &mut orbit_display_gridcell, &mut orbit_display_transform);
+            } else if cache.stale(view, LineSource::Empty) {
+                cache.rebuild(&mut meshes, &orbit_display_mesh3d.0, &[]);
             }
         }
     }
@@ -488,6 +557,118 @@ fn display_state(
     // info!("Display Orbits {:?}", duration);
 }
 
+// Spin each body about its pole to its prime meridian angle, so surface textures line up with longitude. Longitude 0
+// is in the middle of the textures, half a turn from where the sphere mesh's u coordinate starts.
+fn rotate_bodies(state_keeper: Res<StateKeeper>, mut body_query: Query<(&ObjectID, &mut Transform), With<BodyDisplay>>) {
+    let display = display_rotation(&state_keeper);
+    for (object_id, mut transform) in body_query.iter_mut() {
+        let body_fixed = frames::Frame::BodyFixed(object_id.id).at(&state_keeper, state_keeper.current_step).rotation;
+        transform.rotation = Quat::from_mat3(&(display * body_fixed * DMat3::from_rotation_z(PI)).as_mat3());
+    }
+}
+
 fn main_tick(mut state_keeper: ResMut<StateKeeper>) {
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use bevy::asset::AssetPlugin;
+    use crate::scenario::TransferStudy;
+    use super::*;
+
+    // Mean time per frame the display systems may take, standing still or playing
+    const FRAME_BUDGET: Duration = Duration::from_millis(4);
+    const FRAMES: u32 = 100;
+
+    // The display systems without a window or renderer, over a month of the default scenario
+    pub(crate) fn headless_app() -> App {
+        headless_app_with(Scenario {
+            span_days: 30.0,
+            transfer: TransferStudy { departure_window_days: (0.0, 10.0), flight_time_days: (5.0, 20.0), ..Scenario::default().transfer },
+            ..Scenario::default()
+        })
+    }
+
+    pub(crate) fn headless_app_with(scenario: Scenario) -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .init_asset::<Mesh>()
+            .init_asset::<StandardMaterial>()
+            .init_asset::<Image>()
+            .insert_resource(scenario)
+            .add_systems(Startup, (
+                setup,
+                |mut state_keeper: ResMut<StateKeeper>| propagate(&mut state_keeper),
+                (transfer::spawn_transfer_displays, ccsds::spawn_ephemeris_displays),
+            ).chain())
+            .add_systems(Update, (display_state, rotate_bodies, transfer::display_transfer, ccsds::display_ephemerides).chain());
+        app
+    }
+
+    // The Sun, Earth and Mars over the 2026 opportunity, with the default Earth to Mars study, coarse enough to
+    // propagate quickly. TRANSFER_STEPS (at 144 steps a day) is a 300 day Earth to Mars transfer within it.
+    pub(crate) fn transfer_scenario() -> Scenario {
+        Scenario {
+            epoch: "2026-10-01 00:00:00 TDB".to_string(),
+            span_days: 400.0,
+            dt: 600.0,
+            bodies: ["Sun", "Earth", "Mars"].map(String::from).to_vec(),
+            transfer: TransferStudy { departure_window_days: (0.0, 60.0), flight_time_days: (150.0, 330.0), ..Scenario::default().transfer },
+            ..Scenario::default()
+        }
+    }
+    pub(crate) const TRANSFER_STEPS: (u32, u32) = (30 * 144, 330 * 144);
+
+    // Lines are rebuilt (their meshes rewritten in place, which Assets reports as Modified) only when what they're drawn
+    // from changes, so standing still rebuilds nothing and playing adds no meshes
+    #[test]
+    fn display_is_cached_standing_still() {
+        let mut app = headless_app();
+        app.update(); // Startup, and the first build of every line
+        let meshes = app.world().resource::<Assets<Mesh>>().len();
+        let mut cursor = app.world().resource::<Events<AssetEvent<Mesh>>>().get_cursor_current();
+
+        // Events only last two updates, so they're counted as they come
+        let mut rebuilds = 0;
+        for _ in 0..FRAMES {
+            app.update();
+            rebuilds += cursor.read(app.world().resource::<Events<AssetEvent<Mesh>>>())
+                .filter(|event| matches!(event, AssetEvent::Modified { .. }))
+                .count();
+        }
+        assert_eq!(rebuilds, 0, "lines rebuilt standing still");
+
+        for _ in 0..FRAMES {
+            app.world_mut().resource_mut::<StateKeeper>().current_step += 10;
+            app.update();
+        }
+        assert_eq!(app.world().resource::<Assets<Mesh>>().len(), meshes, "display systems added meshes after startup");
+    }
+
+    // Wall clock timing depends on the machine and its load, so this only runs when asked for (cargo test -- --ignored)
+    #[test]
+    #[ignore]
+    fn display_stays_within_frame_budget() {
+        let mut app = headless_app();
+        app.update();
+
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            app.update();
+        }
+        let still = start.elapsed() / FRAMES;
+
+        // Trails follow the current step, so these rebuild some lines every frame
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            app.world_mut().resource_mut::<StateKeeper>().current_step += 10;
+            app.update();
+        }
+        let playing = start.elapsed() / FRAMES;
+
+        assert!(still < FRAME_BUDGET, "{:?} per frame standing still, over the {:?} budget", still, FRAME_BUDGET);
+        assert!(playing < FRAME_BUDGET, "{:?} per frame playing, over the {:?} budget", playing, FRAME_BUDGET);
+    }
+}
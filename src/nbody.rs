@@ -74,6 +74,22 @@ fn add_scaled_body_states(
     ret
 }
 
+// Kick-drift-kick leapfrog. Only second order, but symplectic, so energy doesn't drift over long spans the way it
+// does with RK4.
+pub fn leapfrog_step(body_infos: &BodyInfos, state: &BodyStates, dt: f64) -> BodyStates {
+    let a0 = compute_derivatives(body_infos, state);
+    let mut new_state = state.clone();
+    for (id, body_state) in new_state.iter_mut() {
+        body_state[1] += a0.get(id).unwrap()[1] * dt / 2.0;
+        body_state[0] += body_state[1] * dt;
+    }
+    let a1 = compute_derivatives(body_infos, &new_state);
+    for (id, body_state) in new_state.iter_mut() {
+        body_state[1] += a1.get(id).unwrap()[1] * dt / 2.0;
+    }
+    new_state
+}
+
 #[inline(always)]
 pub fn rk4_step(body_infos: &BodyInfos, state: &BodyStates, dt: f64) -> BodyStates {
     // k1 = f(t, y)
@@ -0,0 +1,161 @@
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use bevy_math::DVec3;
+use crate::*;
+use crate::bodies_init::{AU, GRAVITATIONAL_CONSTANT};
+use crate::camera::{focus_on, CameraState};
+use crate::spheres::{hill_radius, sphere_of_influence};
+// Picking: a left click selects the body whose sphere the cursor's ray meets first, or failing that whose label is
+// under the cursor, and opens its info panel. A second click on the same body within DOUBLE_CLICK_SECONDS focuses the
+// camera on it, and a click on empty space clears the selection. Clicks on buttons are left to the buttons.
+
+const DOUBLE_CLICK_SECONDS: f64 = 0.4;
+
+#[derive(Component)]
+pub struct InfoPanel {}
+
+pub fn spawn_info_panel(mut commands: Commands) {
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(80.0),
+            right: Val::Px(12.0),
+            display: Display::None,
+            ..default()
+        },
+        Text::default(),
+        InfoPanel {},
+    ));
+}
+
+// Distance along the ray (direction normalized) to where it first meets the sphere, if it does
+fn ray_sphere(origin: DVec3, direction: DVec3, center: DVec3, radius: f64) -> Option<f64> {
+    let to_center = center - origin;
+    let along = to_center.dot(direction);
+    let miss_squared = to_center.length_squared() - along * along;
+    if along < 0.0 || miss_squared > radius * radius {
+        return None;
+    }
+    Some(along - (radius * radius - miss_squared).sqrt())
+}
+
+// Whether the cursor is over a label, which camera_controller places by its top left corner
+fn label_contains(node: &Node, computed_node: &ComputedNode, cursor: Vec2) -> bool {
+    let (Val::Px(left), Val::Px(top)) = (node.left, node.top) else {
+        return false;
+    };
+    let size = computed_node.size() * computed_node.inverse_scale_factor();
+    node.display != Display::None && cursor.x >= left && cursor.x <= left + size.x && cursor.y >= top && cursor.y <= top + size.y
+}
+
+pub fn pick_bodies(
+    mut state_keeper: ResMut<StateKeeper>,
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    time: Res<Time<Real>>,
+    window: Single<&Window, With<PrimaryWindow>>,
+    camera: Single<(&Camera, &GlobalTransform, &mut CameraState)>,
+    bodies: Query<(&ObjectID, &GlobalTransform), With<BodyDisplay>>,
+    labels: Query<(&ObjectID, &Node, &ComputedNode), With<BodyOverlayDisplay>>,
+    interactions: Query<&Interaction>,
+    mut last_click: Local<Option<(u32, f64)>>, // Body and time of the last click that picked one
+) {
+    if !mouse_input.just_pressed(MouseButton::Left) || interactions.iter().any(|interaction| *interaction != Interaction::None) {
+        return;
+    }
+    let Some(cursor) = window.cursor_position() else {
+        return;
+    };
+    let (camera, camera_global_transform, mut camera_state) = camera.into_inner();
+    let Ok(ray) = camera.viewport_to_world(camera_global_transform, cursor) else {
+        return;
+    };
+
+    let (origin, direction) = (ray.origin.as_dvec3(), ray.direction.as_vec3().as_dvec3());
+    let picked = bodies.iter()
+        .filter(|(object_id, _)| body_shown(&state_keeper, object_id.id))
+        .filter_map(|(object_id, global_transform)| {
+            let radius = state_keeper.info.get(&object_id.id).unwrap().radius;
+            ray_sphere(origin, direction, global_transform.translation().as_dvec3(), radius).map(|distance| (object_id.id, distance))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(id, _)| id)
+        .or_else(|| {
+            labels.iter()
+                .find(|(_, node, computed_node)| label_contains(node, computed_node, cursor))
+                .map(|(object_id, _, _)| object_id.id)
+        });
+
+    let now = time.elapsed_secs_f64();
+    match picked {
+        Some(id) => {
+            if matches!(*last_click, Some((last, at)) if last == id && now - at < DOUBLE_CLICK_SECONDS) {
+                focus_on(&mut state_keeper, &mut camera_state, id);
+                *last_click = None;
+            } else {
+                *last_click = Some((id, now));
+            }
+            state_keeper.selected = Some(id);
+        }
+        None => {
+            state_keeper.selected = None;
+            *last_click = None;
+        }
+    }
+}
+
+// Highlight the selected and focused bodies' labels, and fill in the selected body's info panel
+pub fn display_selection(
+    state_keeper: Res<StateKeeper>,
+    camera_state: Single<&CameraState>,
+    panel: Single<(&mut Node, &mut Text), With<InfoPanel>>,
+    mut labels: Query<(&ObjectID, &mut BodyOverlayDisplay, &mut TextColor)>,
+) {
+    for (object_id, mut overlay, mut text_color) in labels.iter_mut() {
+        let selected = state_keeper.selected == Some(object_id.id);
+        let focused = camera_state.focused == object_id.id;
+        if overlay.selected != selected || overlay.focused != focused {
+            overlay.selected = selected;
+            overlay.focused = focused;
+            text_color.0 = if selected {
+                Color::srgb(1.0, 0.9, 0.3)
+            } else if focused {
+                Color::srgb(0.5, 0.8, 1.0)
+            } else {
+                Color::WHITE
+            };
+        }
+    }
+
+    let (mut node, mut text) = panel.into_inner();
+    let Some(id) = state_keeper.selected else {
+        node.display = Display::None;
+        return;
+    };
+    node.display = Display::DEFAULT;
+    let states = state_keeper.state.get(&state_keeper.current_step).unwrap();
+    let info = state_keeper.info.get(&id).unwrap();
+    let state = states.get(&id).unwrap();
+    text.0 = format!(
+        "{}{}\nMass: {:.4e} kg\nRadius: {:.1} km",
+        info.name,
+        if camera_state.focused == id { " (focused)" } else { " (double-click to focus)" },
+        info.mu / GRAVITATIONAL_CONSTANT,
+        info.radius / 1000.0,
+    );
+    let parent = info.kepler_parent;
+    if parent != id {
+        let parent_info = state_keeper.info.get(&parent).unwrap();
+        let oe = oe_from_rv(parent_info.mu, &sub_body_state(state, states.get(&parent).unwrap()));
+        text.0 += &format!("\nAbout {}: {}", parent_info.name, oe);
+        text.0 += &format!(
+            "\nSOI: {:.0} km, Hill: {:.0} km (I to show)",
+            sphere_of_influence(&state_keeper, id, state_keeper.current_step) / 1000.0,
+            hill_radius(&state_keeper, id, state_keeper.current_step) / 1000.0,
+        );
+    }
+    // The Sun is body 0, when it's in the scenario
+    if let Some(sun) = states.get(&0).filter(|_| id != 0) {
+        let distance = (state[0] - sun[0]).length();
+        text.0 += &format!("\nDistance to Sun: {:.4e} km ({:.4} AU)", distance / 1000.0, distance / AU);
+    }
+}
@@ -1,9 +1,25 @@
+use chrono::{DateTime, Duration, Utc};
 use plotters::prelude::*;
+use serde::{Deserialize, Serialize};
 
+// What a porkchop plot is drawn from, kept with the run so a cached one draws the same plot: the lowest Δv of the
+// short and long way transfers (rows of flight time, columns of departure day), whether the departure asymptote of
+// each is in reach of the parking orbit, and the step of the first departure column
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Porkchop {
+    pub dv_grid: Vec<Vec<f32>>,
+    pub dla_grid: Vec<Vec<bool>>,
+    pub departure_start: u32,
+}
+
+// Cells where dla_grid is false need a departure plane change (the asymptote is out of reach of the parking orbit),
+// and are drawn darkened. Columns are days of departure from departure_start.
 pub fn make_porkchop_plot(
     dv_grid: &[Vec<f32>],
+    dla_grid: &[Vec<bool>],
     width: u32,
     height: u32,
+    departure_start: DateTime<Utc>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // 1) find global min/max
     let (vmin, vmax) = {
@@ -31,7 +47,8 @@ pub fn make_porkchop_plot(
         .build_cartesian_2d(0u32..width, 0u32..height)?;
     chart
         .configure_mesh()
-        .x_desc("Departure (days from t=0)")
+        .x_desc("Departure (UTC)")
+        .x_label_formatter(&|day| (departure_start + Duration::days(*day as i64)).format("%Y-%m-%d").to_string())
         .y_desc("Flight Time (days)")
         .draw()?;
 
@@ -40,10 +57,11 @@ pub fn make_porkchop_plot(
         for (col_idx, &dv) in row.iter().enumerate() {
 
             let t = ((dv - vmin) / (vmax - vmin)).clamp(0.0, 1.0);
+            let shade = if dla_grid[row_idx][col_idx] { 255.0 } else { 96.0 };
             let color = RGBColor(
-                (t * 255.0) as u8,
+                (t * shade) as u8,
                 0,
-                ((1.0 - t) * 255.0) as u8,
+                ((1.0 - t) * shade) as u8,
             );
 
             let x0 = col_idx as u32;
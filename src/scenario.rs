@@ -0,0 +1,243 @@
+use std::fs;
+use std::path::Path;
+use bevy::prelude::Resource;
+use serde::{Deserialize, Serialize};
+use crate::interplanetary::{ArrivalMode, LaunchSite, OrbitGeometry};
+use crate::keplerian::OE;
+use crate::time::Epoch;
+use crate::{nbody, BodyInfo, BodyInfos, BodyState, BodyStates};
+// Scenario files: everything that decides what gets propagated and studied, in RON, so a run can be shared and
+// reproduced. Pass a path as the first argument, or scenarios/default.ron is used (and if that's missing too, the
+// built in default below, which is the same thing).
+
+pub const DEFAULT_PATH: &str = "scenarios/default.ron";
+
+#[derive(Clone, Serialize, Deserialize, Resource)]
+pub struct Scenario {
+    pub epoch: String, // Of step 0, like "2025-04-01 12:00:00 TDB" (see Epoch::parse)
+    pub span_days: f64,
+    pub dt: f64, // Seconds per step
+    pub integrator: Integrator,
+    pub bodies: Vec<String>, // Names from the catalog in bodies_init, or empty for all of them
+    pub transfer: TransferStudy,
+    pub focus: String, // Body the camera starts on
+    #[serde(default)]
+    pub ephemerides: Vec<String>, // CCSDS OEM files of other trajectories to draw alongside the bodies
+    #[serde(default = "default_trail_days")]
+    pub trail_days: (f64, f64), // Days of propagated path drawn behind and ahead of the current step
+    #[serde(default)]
+    pub ground_track: Option<GroundTrackStudy>, // Drawn with the G key, or else a day of the parking orbit
+}
+
+fn default_trail_days() -> (f64, f64) {
+    (90.0, 30.0)
+}
+
+// The porkchop study run at startup, whose cheapest transfer becomes the initial selection
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TransferStudy {
+    pub departure: String,
+    pub arrival: String,
+    pub departure_window_days: (f64, f64), // From step 0
+    pub flight_time_days: (f64, f64),
+    pub departure_orbit: OrbitGeometry,
+    pub arrival_mode: ArrivalMode,
+    pub launch_site: LaunchSite,
+}
+
+// An orbit around one body whose ground track and coverage are studied over a window of days
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GroundTrackStudy {
+    pub body: String,
+    pub orbit: OE, // In the body's equatorial frame, at the start of the window
+    pub window_days: (f64, f64), // From step 0
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum Integrator {
+    Rk4,
+    Leapfrog,
+}
+
+impl Integrator {
+    pub fn step(&self, body_infos: &BodyInfos, state: &BodyStates, dt: f64) -> BodyStates {
+        match self {
+            Integrator::Rk4 => nbody::rk4_step(body_infos, state, dt),
+            Integrator::Leapfrog => nbody::leapfrog_step(body_infos, state, dt),
+        }
+    }
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Scenario {
+            epoch: "2025-04-01 12:00:00 TDB".to_string(),
+            span_days: 365.0 * 4.0,
+            dt: 100.0,
+            integrator: Integrator::Rk4,
+            bodies: Vec::new(),
+            transfer: TransferStudy {
+                departure: "Earth".to_string(),
+                arrival: "Mars".to_string(),
+                departure_window_days: (0.0, 730.0),
+                flight_time_days: (90.0, 360.0),
+                departure_orbit: OrbitGeometry::circular(180000.0, 0.0),
+                arrival_mode: ArrivalMode::Orbit(OrbitGeometry::circular(180000.0, 0.0)),
+                launch_site: LaunchSite {
+                    latitude: 28.5f64.to_radians(),
+                    azimuth_min: 35.0f64.to_radians(),
+                    azimuth_max: 120.0f64.to_radians(),
+                },
+            },
+            focus: "Sun".to_string(),
+            ephemerides: Vec::new(),
+            trail_days: default_trail_days(),
+            ground_track: None,
+        }
+    }
+}
+
+impl Scenario {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Couldn't read scenario {}: {}", path, e))?;
+        ron::from_str(&text).map_err(|e| format!("Couldn't parse scenario {}: {}", path, e))
+    }
+
+    // The scenario named on the command line, or the default one
+    pub fn from_args() -> Result<Self, String> {
+        match std::env::args().nth(1) {
+            Some(path) => Scenario::load(&path),
+            None if Path::new(DEFAULT_PATH).exists() => Scenario::load(DEFAULT_PATH),
+            None => Ok(Scenario::default()),
+        }
+    }
+
+    pub fn includes(&self, name: &str) -> bool {
+        self.bodies.is_empty() || self.bodies.iter().any(|body| body == name)
+    }
+
+    // Body IDs are positions in the catalog, whether or not every body is included
+    pub fn body_id(&self, catalog: &[(BodyState, BodyInfo)], name: &str) -> Result<u32, String> {
+        match catalog.iter().position(|(_, info)| info.name == name) {
+            Some(id) if self.includes(name) => Ok(id as u32),
+            Some(_) => Err(format!("{} is used by the scenario but not included in its bodies", name)),
+            None => Err(format!("No body called {} in the catalog", name)),
+        }
+    }
+
+    pub fn validate(&self, catalog: &[(BodyState, BodyInfo)]) -> Result<(), String> {
+        Epoch::parse(&self.epoch)?;
+        if self.dt <= 0.0 || self.span_days <= 0.0 {
+            return Err("Scenario span and step must be positive".to_string());
+        }
+        for name in &self.bodies {
+            self.body_id(catalog, name)?;
+        }
+        for (_, info) in catalog.iter().filter(|(_, info)| self.includes(&info.name)) {
+            let parent = &catalog[info.kepler_parent as usize].1.name;
+            if !self.includes(parent) {
+                return Err(format!("{} is included without {}, which it orbits", info.name, parent));
+            }
+        }
+        for name in [&self.transfer.departure, &self.transfer.arrival, &self.focus] {
+            self.body_id(catalog, name)?;
+        }
+        let (first, last) = self.transfer.departure_window_days;
+        let (shortest, longest) = self.transfer.flight_time_days;
+        if first < 0.0 || last < first || shortest <= 0.0 || longest < shortest {
+            return Err("Scenario transfer window and flight times must be increasing ranges".to_string());
+        }
+        // At least the earliest departure on the shortest flight has to arrive inside the propagation
+        let to_steps = |days: f64| (days * self.steps_per_day() as f64) as u32;
+        if to_steps(first) + to_steps(shortest) + 1 >= self.step_limit() {
+            return Err(format!(
+                "Scenario transfer.departure_window_days.0 + transfer.flight_time_days.0 ({} days) must be less than span_days ({} days)",
+                first + shortest, self.span_days
+            ));
+        }
+        if let Some(study) = &self.ground_track {
+            self.body_id(catalog, &study.body)?;
+            let (start, end) = study.window_days;
+            if start < 0.0 || end <= start {
+                return Err("Scenario ground_track.window_days must be an increasing range".to_string());
+            }
+            if study.orbit.a <= 0.0 || !(0.0..1.0).contains(&study.orbit.e) {
+                return Err("Scenario ground_track.orbit must be elliptic".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    // The departure window, ending early enough that the longest flight still arrives within the span
+    pub fn departure_window_days(&self) -> (f64, f64) {
+        let (first, last) = self.transfer.departure_window_days;
+        (first, last.min(self.span_days - self.transfer.flight_time_days.1).max(first))
+    }
+
+    pub fn steps_per_day(&self) -> u32 {
+        (86400.0 / self.dt).round().max(1.0) as u32
+    }
+
+    pub fn step_limit(&self) -> u32 {
+        (self.span_days * 86400.0 / self.dt).round() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bodies_init::planets_info;
+    use crate::keplerian::OE;
+    use super::{GroundTrackStudy, Scenario, TransferStudy, DEFAULT_PATH};
+
+    #[test]
+    fn default_is_valid() {
+        assert!(Scenario::default().validate(&planets_info()).is_ok());
+        assert!(Scenario::load(DEFAULT_PATH).unwrap().validate(&planets_info()).is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_bodies() {
+        let scenario = Scenario { bodies: ["Sun", "Earth", "Marz"].map(String::from).to_vec(), ..Scenario::default() };
+        let e = scenario.validate(&planets_info()).unwrap_err();
+        assert!(e.contains("Marz"), "{}", e);
+
+        let scenario = Scenario { transfer: TransferStudy { arrival: "Vulcan".to_string(), ..Scenario::default().transfer }, ..Scenario::default() };
+        let e = scenario.validate(&planets_info()).unwrap_err();
+        assert!(e.contains("Vulcan"), "{}", e);
+    }
+
+    // The earliest departure on the shortest flight has to arrive within the span
+    #[test]
+    fn rejects_transfers_past_the_span() {
+        let scenario = Scenario { span_days: 80.0, ..Scenario::default() };
+        let e = scenario.validate(&planets_info()).unwrap_err();
+        assert!(e.contains("departure_window_days.0") && e.contains("flight_time_days.0"), "{}", e);
+
+        let fits = Scenario { transfer: TransferStudy { departure_window_days: (5.0, 700.0), ..Scenario::default().transfer }, span_days: 100.0, ..Scenario::default() };
+        assert!(fits.validate(&planets_info()).is_ok());
+        assert_eq!(fits.departure_window_days(), (5.0, 5.0));
+    }
+
+    #[test]
+    fn checks_ground_track_studies() {
+        let study = GroundTrackStudy {
+            body: "Mars".to_string(),
+            orbit: OE { a: 3796000.0, e: 0.0, i: 1.6, f: 0.0, ω: 0.0, Ω: 0.0 },
+            window_days: (300.0, 301.0),
+        };
+        let scenario = Scenario { ground_track: Some(study.clone()), ..Scenario::default() };
+        assert!(scenario.validate(&planets_info()).is_ok());
+
+        let scenario = Scenario { ground_track: Some(GroundTrackStudy { body: "Marz".to_string(), ..study.clone() }), ..Scenario::default() };
+        let e = scenario.validate(&planets_info()).unwrap_err();
+        assert!(e.contains("Marz"), "{}", e);
+
+        let scenario = Scenario { ground_track: Some(GroundTrackStudy { window_days: (301.0, 300.0), ..study.clone() }), ..Scenario::default() };
+        let e = scenario.validate(&planets_info()).unwrap_err();
+        assert!(e.contains("window_days"), "{}", e);
+
+        let scenario = Scenario { ground_track: Some(GroundTrackStudy { orbit: OE { e: 1.5, ..study.orbit.clone() }, ..study }), ..Scenario::default() };
+        let e = scenario.validate(&planets_info()).unwrap_err();
+        assert!(e.contains("elliptic"), "{}", e);
+    }
+}
@@ -0,0 +1,126 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::interplanetary::Interplanetary;
+use crate::scenario::Scenario;
+use crate::{StateKeeper, TimeStates};
+// Cached runs: the propagated ephemeris, the interplanetaries, the porkchop grid and the transfer selection, written
+// with bincode after populate_state finishes and read back on the next launch instead of recomputing. Each run is
+// keyed by a hash of everything it was computed from (the scenario, and the catalog entries of the bodies in it), so
+// a cache file is only ever loaded for identical inputs. A full four year run is a few hundred MB; delete cache/ to clear them.
+
+pub const CACHE_DIR: &str = "cache";
+const MAGIC: &[u8; 8] = b"SMSNAPSH";
+// Bump whenever the layout below, or the propagation or transfer code, changes what a run would contain
+const VERSION: u32 = 2;
+
+// FNV-1a, 64 bit. Unlike std's hasher it's the same on every platform and compiler, which a file name key needs.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Fnv1a(0xcbf29ce484222325)
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn f64s(&mut self, values: &[f64]) {
+        for value in values {
+            self.bytes(&value.to_le_bytes());
+        }
+    }
+}
+
+// Hash of the inputs to populate_state, read from the state keeper before anything is propagated
+pub fn key(state_keeper: &StateKeeper) -> u64 {
+    let mut hash = Fnv1a::new();
+    hash.bytes(&VERSION.to_le_bytes());
+    // What's only drawn doesn't change what's computed
+    let scenario = Scenario { ephemerides: Vec::new(), trail_days: (0.0, 0.0), ground_track: None, ..state_keeper.scenario.clone() };
+    hash.bytes(ron::to_string(&scenario).unwrap().as_bytes());
+    let initial = state_keeper.state.get(&0).unwrap();
+    let mut ids: Vec<&u32> = state_keeper.info.keys().collect();
+    ids.sort();
+    for id in ids {
+        let info = state_keeper.info.get(id).unwrap();
+        let [r, v] = initial.get(id).unwrap();
+        hash.bytes(&id.to_le_bytes());
+        hash.bytes(info.name.as_bytes());
+        hash.f64s(&r.to_array());
+        hash.f64s(&v.to_array());
+        hash.f64s(&[info.mu, info.radius, info.j2, info.rotational_rate, info.prime_meridian]);
+        hash.f64s(&info.tilt.to_array());
+        hash.bytes(&[info.affected as u8, info.affects as u8, info.display_as_keplerian as u8]);
+        hash.bytes(&info.kepler_parent.to_le_bytes());
+    }
+    hash.0
+}
+
+pub fn path(key: u64) -> String {
+    format!("{}/{:016x}.snapshot", CACHE_DIR, key)
+}
+
+pub fn save(state_keeper: &StateKeeper, key: u64) -> Result<(), String> {
+    fs::create_dir_all(CACHE_DIR).map_err(|e| e.to_string())?;
+    // Written next to the real name and renamed at the end, so an interrupted save never leaves a partial run behind
+    let partial = format!("{}.partial", path(key));
+    let mut file = BufWriter::new(File::create(&partial).map_err(|e| e.to_string())?);
+    file.write_all(MAGIC).map_err(|e| e.to_string())?;
+    write(&mut file, &VERSION)?;
+    write(&mut file, &key)?;
+    write(&mut file, &state_keeper.last_step_computed)?;
+    write(&mut file, &state_keeper.current_step)?;
+    write(&mut file, &state_keeper.interplanetary_selection)?;
+    write(&mut file, &state_keeper.interplanetary)?;
+    write(&mut file, &state_keeper.interplanetaries)?;
+    write(&mut file, &state_keeper.porkchop)?;
+    write(&mut file, &state_keeper.state)?;
+    file.flush().map_err(|e| e.to_string())?;
+    drop(file);
+    fs::rename(&partial, path(key)).map_err(|e| e.to_string())
+}
+
+// Fills in the state keeper from the cached run for key, if there is one. Nothing is touched unless the whole file
+// reads back.
+pub fn load(state_keeper: &mut StateKeeper, key: u64) -> Result<(), String> {
+    let mut file = BufReader::new(File::open(path(key)).map_err(|e| e.to_string())?);
+    let mut magic = [0u8; 8];
+    file.read_exact(&mut magic).map_err(|e| e.to_string())?;
+    if &magic != MAGIC {
+        return Err("not a snapshot".to_string());
+    }
+    if read::<u32>(&mut file)? != VERSION || read::<u64>(&mut file)? != key {
+        return Err("written for other inputs".to_string());
+    }
+    let last_step_computed: u32 = read(&mut file)?;
+    let current_step: u32 = read(&mut file)?;
+    let selection: (u32, u32, u32, u32, bool) = read(&mut file)?;
+    let interplanetary: Option<Interplanetary> = read(&mut file)?;
+    let interplanetaries = read(&mut file)?;
+    let porkchop = read(&mut file)?;
+    let state: TimeStates = read(&mut file)?;
+    if !state.contains_key(&last_step_computed) {
+        return Err("ephemeris is truncated".to_string());
+    }
+    state_keeper.last_step_computed = last_step_computed;
+    state_keeper.current_step = current_step;
+    state_keeper.interplanetary_selection = selection;
+    state_keeper.interplanetary = interplanetary;
+    state_keeper.interplanetaries = interplanetaries;
+    state_keeper.porkchop = porkchop;
+    state_keeper.state = state;
+    Ok(())
+}
+
+fn write<T: Serialize>(file: &mut BufWriter<File>, value: &T) -> Result<(), String> {
+    bincode::serialize_into(file, value).map_err(|e| e.to_string())
+}
+
+fn read<T: DeserializeOwned>(file: &mut BufReader<File>) -> Result<T, String> {
+    bincode::deserialize_from(file).map_err(|e| e.to_string())
+}
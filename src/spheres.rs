@@ -0,0 +1,203 @@
+use bevy::pbr::NotShadowCaster;
+use bevy::prelude::*;
+use bevy_math::DVec3;
+use big_space::prelude::*;
+use crate::camera::CameraState;
+use crate::interplanetary::Interplanetary;
+use crate::time::step_to_epoch;
+use crate::transfer::spacecraft_position;
+use crate::{body_shown, StateKeeper};
+// Spheres of influence (Laplace's, which the patched conics in interplanetary() switch bodies at) and Hill spheres
+// (where a body's gravity holds on to satellites), both from the body's current distance to its parent. I shows or
+// hides them around the selected body (or the focused one, with nothing selected). Whenever a transfer is selected,
+// where its spacecraft crosses into and out of each sphere of influence along the way is logged.
+
+const CROSSING_SAMPLES: u32 = 20000;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SphereKind {
+    Influence,
+    Hill,
+}
+
+#[derive(Component)]
+pub struct SphereDisplay {
+    body: u32,
+    kind: SphereKind,
+}
+
+fn parent_distance(state_keeper: &StateKeeper, body: u32, parent: u32, step: u32) -> f64 {
+    let states = state_keeper.state.get(&step).unwrap();
+    (states.get(&body).unwrap()[0] - states.get(&parent).unwrap()[0]).length()
+}
+
+// Laplace's sphere of influence, a (m / M)^(2/5), with a the body's distance from its parent at the step
+pub fn sphere_of_influence(state_keeper: &StateKeeper, body: u32, step: u32) -> f64 {
+    let info = state_keeper.info.get(&body).unwrap();
+    let parent = info.kepler_parent;
+    if parent == body {
+        return f64::INFINITY;
+    }
+    parent_distance(state_keeper, body, parent, step) * (info.mu / state_keeper.info.get(&parent).unwrap().mu).powf(0.4)
+}
+
+// The Hill sphere, a (m / 3M)^(1/3), with a the body's distance from its parent at the step
+pub fn hill_radius(state_keeper: &StateKeeper, body: u32, step: u32) -> f64 {
+    let info = state_keeper.info.get(&body).unwrap();
+    let parent = info.kepler_parent;
+    if parent == body {
+        return f64::INFINITY;
+    }
+    parent_distance(state_keeper, body, parent, step) * (info.mu / (3.0 * state_keeper.info.get(&parent).unwrap().mu)).cbrt()
+}
+
+fn radius(state_keeper: &StateKeeper, body: u32, kind: SphereKind) -> f64 {
+    match kind {
+        SphereKind::Influence => sphere_of_influence(state_keeper, body, state_keeper.current_step),
+        SphereKind::Hill => hill_radius(state_keeper, body, state_keeper.current_step),
+    }
+}
+
+// A unit sphere for each of every body's spheres (other than bodies without a parent), in the body's own grid and
+// scaled to size each frame
+pub fn spawn_sphere_displays(
+    mut commands: Commands,
+    state_keeper: Res<StateKeeper>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let mesh = meshes.add(Sphere::new(1.0).mesh().uv(48, 24));
+    let material = |color: Color| StandardMaterial {
+        base_color: color,
+        alpha_mode: AlphaMode::Blend,
+        unlit: true,
+        double_sided: true,
+        cull_mode: None, // Seen from inside, too
+        ..default()
+    };
+    let influence = materials.add(material(Color::srgba(0.2, 0.8, 1.0, 0.08)));
+    let hill = materials.add(material(Color::srgba(1.0, 0.3, 0.8, 0.05)));
+    for (id, info) in state_keeper.info.iter() {
+        if info.kepler_parent == *id {
+            continue;
+        }
+        for (kind, material) in [(SphereKind::Influence, influence.clone()), (SphereKind::Hill, hill.clone())] {
+            let sphere = commands.spawn((
+                Mesh3d(mesh.clone()),
+                MeshMaterial3d(material),
+                Transform::default(),
+                GridCell::<i64>::default(),
+                Visibility::Hidden,
+                NotShadowCaster,
+                SphereDisplay { body: *id, kind },
+            )).id();
+            commands.entity(info.body_display_grid_id.unwrap()).add_child(sphere);
+        }
+    }
+}
+
+// Press I to show or hide the spheres around the selected (or focused) body
+pub fn toggle_spheres(keys: Res<ButtonInput<KeyCode>>, mut state_keeper: ResMut<StateKeeper>, camera_state: Single<&CameraState>) {
+    if !keys.just_pressed(KeyCode::KeyI) {
+        return;
+    }
+    let body = state_keeper.selected.unwrap_or(camera_state.focused);
+    if !state_keeper.spheres.remove(&body) {
+        state_keeper.spheres.insert(body);
+    }
+}
+
+pub fn display_spheres(state_keeper: Res<StateKeeper>, mut spheres: Query<(&SphereDisplay, &mut Transform, &mut Visibility)>) {
+    for (sphere, mut transform, mut visibility) in spheres.iter_mut() {
+        let shown = state_keeper.spheres.contains(&sphere.body) && body_shown(&state_keeper, sphere.body);
+        visibility.set_if_neq(if shown { Visibility::Inherited } else { Visibility::Hidden });
+        if shown {
+            transform.scale = Vec3::splat(radius(&state_keeper, sphere.body, sphere.kind) as f32);
+        }
+    }
+}
+
+// Steps over the transfer at which the spacecraft enters (true) or leaves (false) a body's sphere of influence,
+// sampled at up to CROSSING_SAMPLES steps. Spheres it starts out in aren't counted as entered.
+pub fn soi_crossings(state_keeper: &StateKeeper, ip: &Interplanetary) -> Vec<(u32, u32, bool)> {
+    let mut bodies: Vec<u32> = state_keeper.info.iter().filter(|(id, info)| info.kepler_parent != **id).map(|(id, _)| *id).collect();
+    bodies.sort();
+    let inside = |step: u32, position: DVec3| -> Vec<bool> {
+        let states = state_keeper.state.get(&step).unwrap();
+        bodies.iter()
+            .map(|body| (position - states.get(body).unwrap()[0]).length() < sphere_of_influence(state_keeper, *body, step))
+            .collect()
+    };
+    let last = ip.arrival_step.min(state_keeper.last_step_computed);
+    let stride = ((last.saturating_sub(ip.departure_step)) / CROSSING_SAMPLES).max(1);
+    let mut steps: Vec<u32> = (ip.departure_step..=last).step_by(stride as usize).collect();
+    if steps.last() != Some(&last) {
+        steps.push(last);
+    }
+
+    let mut crossings = Vec::new();
+    let mut was_inside: Option<Vec<bool>> = None;
+    for step in steps {
+        let Some(position) = spacecraft_position(state_keeper, ip, step) else {
+            continue;
+        };
+        let now_inside = inside(step, position);
+        if let Some(was_inside) = &was_inside {
+            for (i, body) in bodies.iter().enumerate() {
+                if now_inside[i] != was_inside[i] {
+                    crossings.push((step, *body, now_inside[i]));
+                }
+            }
+        }
+        was_inside = Some(now_inside);
+    }
+    crossings
+}
+
+// Log the selected transfer's sphere of influence crossings whenever the selection changes
+pub fn log_soi_crossings(state_keeper: Res<StateKeeper>, mut logged: Local<Option<(u32, u32)>>) {
+    let Some(ip) = &state_keeper.interplanetary else {
+        return;
+    };
+    if *logged == Some((ip.departure_step, ip.arrival_step)) {
+        return;
+    }
+    *logged = Some((ip.departure_step, ip.arrival_step));
+    for (step, body, entering) in soi_crossings(&state_keeper, ip) {
+        let info = state_keeper.info.get(&body).unwrap();
+        info!(
+            "Spacecraft {} {}'s sphere of influence ({:.0} km) at {}",
+            if entering { "enters" } else { "leaves" },
+            info.name,
+            sphere_of_influence(&state_keeper, body, step) / 1000.0,
+            step_to_epoch(&state_keeper, step),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interplanetary::interplanetary;
+    use crate::StateKeeper;
+    use super::soi_crossings;
+
+    // A 300 day Earth to Mars transfer leaves Earth's sphere within days of departure and enters Mars' within days of
+    // arrival, once each
+    #[test]
+    fn transfer_crosses_each_sphere_once() {
+        let mut app = crate::tests::headless_app_with(crate::tests::transfer_scenario());
+        app.update();
+        let state_keeper = app.world().resource::<StateKeeper>();
+        let (earth, mars) = (state_keeper.interplanetary_selection.0, state_keeper.interplanetary_selection.1);
+        let (departure_step, arrival_step) = crate::tests::TRANSFER_STEPS;
+        let ip = interplanetary(state_keeper, departure_step, arrival_step, earth, mars, true);
+
+        let crossings = soi_crossings(state_keeper, &ip);
+        let bodies: Vec<(u32, bool)> = crossings.iter().map(|&(_, body, entering)| (body, entering)).collect();
+        assert_eq!(bodies, [(earth, false), (mars, true)]);
+        let days = |steps: u32| steps as f64 / state_keeper.scenario.steps_per_day() as f64;
+        let (left, entered) = (crossings[0].0, crossings[1].0);
+        assert!(left > departure_step && days(left - departure_step) < 10.0, "{} days", days(left - departure_step));
+        assert!(entered < arrival_step && days(arrival_step - entered) < 10.0, "{} days", days(arrival_step - entered));
+    }
+}
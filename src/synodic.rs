@@ -0,0 +1,156 @@
+use std::f64::consts::PI;
+use bevy::prelude::*;
+use bevy_math::DVec3;
+use big_space::prelude::*;
+use crate::camera::{viewport_position, CameraState};
+use crate::cr3bp::Cr3bp;
+use crate::frames::Frame;
+use crate::{RootGrid, StateKeeper};
+// Rotating (synodic) display mode. With a pair of bodies chosen, the whole scene is drawn in their rotating frame
+// (barycenter at the origin, +X toward the secondary), trails show paths as seen from that frame, and the pair's
+// Lagrange points are marked.
+
+#[derive(Component)]
+pub struct LagrangePoint {
+    pub index: usize,
+}
+
+#[derive(Component)]
+pub struct LagrangeLabel {
+    pub index: usize,
+}
+
+// x coordinates of L1, L2 and L3 in the normalized rotating frame, where the primary is at -mu, the secondary at
+// 1 - mu, and mu is the secondary's share of the pair's mass. Each is the root of the x component of the effective
+// gravity along the x axis, found by Newton's method from the usual Hill sphere approximations.
+pub fn collinear_points(mu: f64) -> [f64; 3] {
+    let hill = (mu / 3.0).cbrt();
+    [1.0 - mu - hill, 1.0 - mu + hill, -1.0 - 5.0 * mu / 12.0].map(|mut x| {
+        for _ in 0..50 {
+            let r1 = x + mu;
+            let r2 = x - 1.0 + mu;
+            let f = x - (1.0 - mu) * r1 / r1.abs().powi(3) - mu * r2 / r2.abs().powi(3);
+            let df = 1.0 + 2.0 * (1.0 - mu) / r1.abs().powi(3) + 2.0 * mu / r2.abs().powi(3);
+            let step = f / df;
+            x -= step;
+            if step.abs() < 1e-15 {
+                break;
+            }
+        }
+        x
+    })
+}
+
+// L1 through L5 of a pair, in normalized rotating frame coordinates
+pub fn lagrange_points(mu: f64) -> [DVec3; 5] {
+    let [l1, l2, l3] = collinear_points(mu);
+    [
+        DVec3::new(l1, 0.0, 0.0),
+        DVec3::new(l2, 0.0, 0.0),
+        DVec3::new(l3, 0.0, 0.0),
+        DVec3::new(0.5 - mu, 3f64.sqrt() / 2.0, 0.0),
+        DVec3::new(0.5 - mu, -3f64.sqrt() / 2.0, 0.0),
+    ]
+}
+
+// L1 through L5 of a pair at a step, in meters in their synodic frame
+pub fn current_lagrange_points(state_keeper: &StateKeeper, step: u32, primary: u32, secondary: u32) -> [DVec3; 5] {
+    let system = Cr3bp::from_state(state_keeper, step, primary, secondary);
+    system.lagrange_points().map(|point| point * system.length)
+}
+
+// Range of steps to draw trails over: one revolution of the pair, centered on the current step
+pub fn trail_window(state_keeper: &StateKeeper, primary: u32, secondary: u32) -> (u32, u32) {
+    let angular_velocity = Frame::Synodic(primary, secondary).at(state_keeper, state_keeper.current_step).angular_velocity;
+    let half = (PI / angular_velocity.length() / state_keeper.dt) as u32;
+    (
+        state_keeper.current_step.saturating_sub(half),
+        (state_keeper.current_step + half).min(state_keeper.last_step_computed),
+    )
+}
+
+// A body's path in the synodic frame over [first, last], with up to samples points
+pub fn synodic_trail(state_keeper: &StateKeeper, primary: u32, secondary: u32, body: u32, first: u32, last: u32, samples: u32) -> Vec<DVec3> {
+    let frame = Frame::Synodic(primary, secondary);
+    let stride = ((last - first) / samples.max(1)).max(1);
+    (first..=last).step_by(stride as usize)
+        .map(|step| frame.transform_from_icrf(state_keeper, step, state_keeper.state.get(&step).unwrap().get(&body).unwrap())[0])
+        .collect()
+}
+
+pub fn spawn_lagrange_markers(mut commands: Commands, root_grid: Single<Entity, With<RootGrid>>) {
+    for index in 0..5 {
+        let anchor = commands.spawn((
+            Transform::default(),
+            GridCell::<i64>::default(),
+            LagrangePoint { index },
+        )).id();
+        commands.entity(*root_grid).add_child(anchor);
+
+        commands.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                display: Display::None,
+                ..default()
+            },
+            Text(format!("L{}", index + 1)),
+            TextColor(Color::srgb(1.0, 0.8, 0.2)),
+            LagrangeLabel { index },
+        ));
+    }
+}
+
+// Press R to draw the scene in the rotating frame of the focused body and its parent, and again to go back
+pub fn toggle_synodic(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut state_keeper: ResMut<StateKeeper>,
+    mut camera_state: Single<&mut CameraState>,
+) {
+    if !keys.just_pressed(KeyCode::KeyR) {
+        return;
+    }
+    let focused = camera_state.focused;
+    let parent = state_keeper.info.get(&focused).unwrap().kepler_parent;
+    if state_keeper.synodic.is_some() {
+        state_keeper.synodic = None;
+        camera_state.frame = Frame::BodyInertial(focused);
+    } else if parent != focused {
+        state_keeper.synodic = Some((parent, focused));
+        camera_state.frame = Frame::Synodic(parent, focused);
+    }
+}
+
+// Place the Lagrange point anchors in the scene, and their labels on screen
+pub fn display_lagrange_points(
+    state_keeper: Res<StateKeeper>,
+    root_grid: Single<&Grid<i64>, With<RootGrid>>,
+    camera: Single<(&Camera, &CameraState)>,
+    mut anchors: Query<(&LagrangePoint, &mut GridCell<i64>, &mut Transform)>,
+    mut labels: Query<(&LagrangeLabel, &mut Node)>,
+) {
+    let (camera, camera_state) = camera.into_inner();
+    let Some((primary, secondary)) = state_keeper.synodic else {
+        for (_label, mut node) in labels.iter_mut() {
+            node.display = Display::None;
+        }
+        return;
+    };
+
+    let points = current_lagrange_points(&state_keeper, state_keeper.current_step, primary, secondary);
+    let mut viewport = [None; 5];
+    for (anchor, mut grid_cell, mut transform) in anchors.iter_mut() {
+        let (new_grid_cell, new_translation) = root_grid.translation_to_grid(points[anchor.index]);
+        *grid_cell = new_grid_cell;
+        transform.translation = new_translation;
+        viewport[anchor.index] = viewport_position(camera, camera_state, points[anchor.index]);
+    }
+    for (label, mut node) in labels.iter_mut() {
+        if let Some(position) = viewport[label.index] {
+            node.display = Display::DEFAULT;
+            node.top = Val::Px(position.y);
+            node.left = Val::Px(position.x);
+        } else {
+            node.display = Display::None;
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use std::fmt;
+use std::sync::OnceLock;
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+use crate::StateKeeper;
+// Time scales. The ephemeris is propagated in TDB and dates are shown in UTC, so converting between them goes
+// UTC -> TAI (leap seconds) -> TT (a fixed 32.184 s) -> TDB (periodic terms under 2 ms).
+// https://www.iers.org/IERS/EN/Publications/TechnicalNotes/tn36.html (IERS Conventions 2010, chapter 10)
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TimeScale {
+    Utc,
+    Tai,
+    Tt,
+    Tdb,
+}
+
+pub const J2000_JD: f64 = 2451545.0;
+pub const MJD_OFFSET: f64 = 2400000.5;
+const TT_MINUS_TAI: f64 = 32.184;
+
+// TAI - UTC from 1972 on, one "MJD date offset" line per leap second
+const LEAP_SECOND_TABLE: &str = include_str!("../assets/leap_seconds.txt");
+
+fn leap_seconds() -> &'static [(f64, f64)] {
+    static TABLE: OnceLock<Vec<(f64, f64)>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        LEAP_SECOND_TABLE.lines()
+            .filter(|line| !line.starts_with('#') && !line.trim().is_empty())
+            .map(|line| {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                (fields[0].parse().unwrap(), fields[2].parse().unwrap())
+            })
+            .collect()
+    })
+}
+
+// TAI - UTC at a UTC modified Julian date. Before 1972 UTC was steered with fractional offsets, which are left out.
+fn tai_minus_utc(utc_mjd: f64) -> f64 {
+    leap_seconds().iter().rev().find(|(mjd, _)| utc_mjd >= *mjd).map_or(10.0, |(_, offset)| *offset)
+}
+
+// TDB - TT, the leading terms of the Earth's orbit around the barycenter, t in TT seconds past J2000
+fn tdb_minus_tt(t: f64) -> f64 {
+    let g = (357.53 + 0.98560028 * t / 86400.0).to_radians();
+    0.001657 * g.sin() + 0.000014 * (2.0 * g).sin()
+}
+
+fn j2000_calendar() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2000, 1, 1).unwrap().and_hms_opt(12, 0, 0).unwrap()
+}
+
+// An instant, kept as TDB seconds past J2000
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub struct Epoch {
+    tdb: f64,
+}
+
+impl Epoch {
+    // From seconds past 2000 Jan 01 12:00:00 as read on a clock of the given scale. UTC seconds are counted like Unix
+    // time, without the leap seconds.
+    pub fn from_seconds(scale: TimeScale, seconds: f64) -> Self {
+        let tt = match scale {
+            TimeScale::Tdb => return Epoch { tdb: seconds },
+            TimeScale::Tt => seconds,
+            TimeScale::Tai => seconds + TT_MINUS_TAI,
+            TimeScale::Utc => seconds + tai_minus_utc(J2000_JD - MJD_OFFSET + seconds / 86400.0) + TT_MINUS_TAI,
+        };
+        Epoch { tdb: tt + tdb_minus_tt(tt) }
+    }
+
+    pub fn seconds(&self, scale: TimeScale) -> f64 {
+        if scale == TimeScale::Tdb {
+            return self.tdb;
+        }
+        let tt = self.tdb - tdb_minus_tt(self.tdb);
+        let tai = tt - TT_MINUS_TAI;
+        match scale {
+            TimeScale::Tt => tt,
+            TimeScale::Tai => tai,
+            // The offset is looked up by UTC date, so guess with the TAI date and correct it once
+            _ => {
+                let guess = tai - tai_minus_utc(J2000_JD - MJD_OFFSET + tai / 86400.0);
+                tai - tai_minus_utc(J2000_JD - MJD_OFFSET + guess / 86400.0)
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn from_julian_date(scale: TimeScale, jd: f64) -> Self {
+        Epoch::from_seconds(scale, (jd - J2000_JD) * 86400.0)
+    }
+
+    pub fn julian_date(&self, scale: TimeScale) -> f64 {
+        J2000_JD + self.seconds(scale) / 86400.0
+    }
+
+    #[cfg(test)]
+    pub fn modified_julian_date(&self, scale: TimeScale) -> f64 {
+        self.julian_date(scale) - MJD_OFFSET
+    }
+
+    // A calendar date and time read on a clock of the given scale
+    pub fn from_calendar(scale: TimeScale, date: NaiveDateTime) -> Self {
+        Epoch::from_seconds(scale, (date - j2000_calendar()).num_microseconds().unwrap() as f64 / 1e6)
+    }
+
+    pub fn calendar(&self, scale: TimeScale) -> NaiveDateTime {
+        j2000_calendar() + Duration::microseconds((self.seconds(scale) * 1e6).round() as i64)
+    }
+
+    pub fn from_utc(date: DateTime<Utc>) -> Self {
+        Epoch::from_calendar(TimeScale::Utc, date.naive_utc())
+    }
+
+    pub fn to_utc(&self) -> DateTime<Utc> {
+        self.calendar(TimeScale::Utc).and_utc()
+    }
+
+    // "2025-04-01 12:00:00 TDB", or with a T between date and time, fractional seconds, and UTC, TAI or TT
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (date, scale) = text.trim().rsplit_once(' ').ok_or(format!("No time scale in \"{}\"", text))?;
+        let scale = match scale {
+            "UTC" => TimeScale::Utc,
+            "TAI" => TimeScale::Tai,
+            "TT" => TimeScale::Tt,
+            "TDB" => TimeScale::Tdb,
+            _ => return Err(format!("Unknown time scale \"{}\"", scale)),
+        };
+        let date = NaiveDateTime::parse_from_str(&date.replace('T', " "), "%Y-%m-%d %H:%M:%S%.f")
+            .map_err(|e| format!("Couldn't read date \"{}\": {}", date, e))?;
+        Ok(Epoch::from_calendar(scale, date))
+    }
+
+    pub fn add_seconds(&self, seconds: f64) -> Self {
+        Epoch { tdb: self.tdb + seconds }
+    }
+
+    // TDB seconds from other to self
+    pub fn seconds_since(&self, other: &Epoch) -> f64 {
+        self.tdb - other.tdb
+    }
+}
+
+impl fmt::Display for Epoch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} UTC", self.calendar(TimeScale::Utc).format("%Y-%m-%d %H:%M:%S%.3f"))
+    }
+}
+
+// Step to epoch and back, with step 0 at state_keeper.epoch. Every date shown or written goes through these.
+pub fn step_to_epoch(state_keeper: &StateKeeper, step: u32) -> Epoch {
+    state_keeper.epoch.add_seconds(step as f64 * state_keeper.dt)
+}
+
+// Nearest step, which may be out of the propagated range
+pub fn epoch_to_step(state_keeper: &StateKeeper, epoch: &Epoch) -> u32 {
+    (epoch.seconds_since(&state_keeper.epoch) / state_keeper.dt).round().max(0.0) as u32
+}
+
+pub fn step_to_date(state_keeper: &StateKeeper, step: u32) -> DateTime<Utc> {
+    step_to_epoch(state_keeper, step).to_utc()
+}
+
+pub fn date_to_step(state_keeper: &StateKeeper, date: DateTime<Utc>) -> u32 {
+    epoch_to_step(state_keeper, &Epoch::from_utc(date))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use crate::StateKeeper;
+    use super::*;
+
+    fn utc(text: &str) -> Epoch {
+        Epoch::parse(&format!("{} UTC", text)).unwrap()
+    }
+
+    #[test]
+    fn j2000_in_tt() {
+        let epoch = Epoch::parse("2000-01-01 12:00:00 TT").unwrap();
+        assert!((epoch.julian_date(TimeScale::Tt) - J2000_JD).abs() < 1e-9, "{}", epoch.julian_date(TimeScale::Tt));
+    }
+
+    // Noon on 2025-04-01 is JD 2460767.0, MJD 60766.5, read on any scale's clock
+    #[test]
+    fn julian_date_round_trip() {
+        for scale in [TimeScale::Utc, TimeScale::Tai, TimeScale::Tt, TimeScale::Tdb] {
+            let epoch = Epoch::from_julian_date(scale, 2460767.0);
+            let noon = NaiveDate::from_ymd_opt(2025, 4, 1).unwrap().and_hms_opt(12, 0, 0).unwrap();
+            assert_eq!(epoch.calendar(scale), noon);
+            assert!((epoch.julian_date(scale) - 2460767.0).abs() < 1e-9, "{}", epoch.julian_date(scale));
+            assert!((epoch.modified_julian_date(scale) - 60766.5).abs() < 1e-9, "{}", epoch.modified_julian_date(scale));
+            let back = Epoch::from_julian_date(scale, epoch.julian_date(scale));
+            assert!(back.seconds_since(&epoch).abs() < 1e-4, "{} s", back.seconds_since(&epoch));
+        }
+    }
+
+    #[test]
+    fn scale_offsets() {
+        for (date, leap_seconds) in [("2000-06-01 00:00:00", 32.0), ("2017-06-01 00:00:00", 37.0), ("2024-01-01 00:00:00", 37.0)] {
+            let epoch = utc(date);
+            let tai_minus_utc = epoch.seconds(TimeScale::Tai) - epoch.seconds(TimeScale::Utc);
+            assert!((tai_minus_utc - leap_seconds).abs() < 1e-6, "TAI - UTC = {} s on {}", tai_minus_utc, date);
+            let tt_minus_tai = epoch.seconds(TimeScale::Tt) - epoch.seconds(TimeScale::Tai);
+            assert!((tt_minus_tai - 32.184).abs() < 1e-6, "TT - TAI = {} s on {}", tt_minus_tai, date);
+        }
+    }
+
+    // The leap second at the end of 2016 makes the last UTC second of the year two seconds long in TAI, and dates on
+    // either side of it read back unchanged
+    #[test]
+    fn leap_second_boundary() {
+        let before = utc("2016-12-31 23:59:59");
+        let after = utc("2017-01-01 00:00:00");
+        assert!((after.seconds(TimeScale::Tai) - before.seconds(TimeScale::Tai) - 2.0).abs() < 1e-6);
+        assert_eq!(before.calendar(TimeScale::Utc), NaiveDate::from_ymd_opt(2016, 12, 31).unwrap().and_hms_opt(23, 59, 59).unwrap());
+        assert_eq!(after.calendar(TimeScale::Utc), NaiveDate::from_ymd_opt(2017, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn steps_round_trip() {
+        let mut app = crate::tests::headless_app();
+        app.update();
+        let state_keeper = app.world().resource::<StateKeeper>();
+        for step in [0, 1, 1000, state_keeper.step_limit - 1] {
+            assert_eq!(epoch_to_step(state_keeper, &step_to_epoch(state_keeper, step)), step);
+        }
+        // Within half a step of one rounds to it
+        let nudged = step_to_epoch(state_keeper, 500).add_seconds(0.4 * state_keeper.dt);
+        assert_eq!(epoch_to_step(state_keeper, &nudged), 500);
+    }
+}
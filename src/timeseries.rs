@@ -0,0 +1,148 @@
+use std::error::Error;
+use std::path::Path;
+use bevy::prelude::{error, info, ButtonInput, KeyCode, Res};
+use chrono::{DateTime, Utc};
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use crate::keplerian::oe_from_rv;
+use crate::time::{date_to_step, step_to_date};
+use crate::{sub_body_state, StateKeeper};
+// Time series plots of orbital quantities for chosen bodies, each relative to its kepler_parent, against date
+
+#[derive(Clone, Copy)]
+pub enum Quantity {
+    SemiMajorAxis,
+    Eccentricity,
+    Inclination,
+    ParentDistance,
+    ParentRelativeVelocity,
+}
+
+impl Quantity {
+    pub const ALL: [Quantity; 5] = [
+        Quantity::SemiMajorAxis,
+        Quantity::Eccentricity,
+        Quantity::Inclination,
+        Quantity::ParentDistance,
+        Quantity::ParentRelativeVelocity,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Quantity::SemiMajorAxis => "a (km)",
+            Quantity::Eccentricity => "e",
+            Quantity::Inclination => "i (deg)",
+            Quantity::ParentDistance => "Distance to parent (km)",
+            Quantity::ParentRelativeVelocity => "Velocity rel. parent (km/s)",
+        }
+    }
+
+    pub fn sample(&self, state_keeper: &StateKeeper, step: u32, body: u32) -> f64 {
+        let parent = state_keeper.info.get(&body).unwrap().kepler_parent;
+        let states = state_keeper.state.get(&step).unwrap();
+        let relative = sub_body_state(states.get(&body).unwrap(), states.get(&parent).unwrap());
+        let mu = state_keeper.info.get(&parent).unwrap().mu;
+        match self {
+            Quantity::SemiMajorAxis => oe_from_rv(mu, &relative).a / 1000.0,
+            Quantity::Eccentricity => oe_from_rv(mu, &relative).e,
+            Quantity::Inclination => oe_from_rv(mu, &relative).i.to_degrees(),
+            Quantity::ParentDistance => relative[0].length() / 1000.0,
+            Quantity::ParentRelativeVelocity => relative[1].length() / 1000.0,
+        }
+    }
+}
+
+// One panel per quantity, with a series per body, sampled at `samples` evenly spaced steps between start and end
+// (clamped to the propagated span). Bodies without a parent (the Sun) are skipped. Writes an SVG if path ends in .svg,
+// and a PNG otherwise.
+pub fn make_timeseries_plot(
+    state_keeper: &StateKeeper,
+    path: &str,
+    bodies: &[u32],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    quantities: &[Quantity],
+    samples: u32,
+) -> Result<(), Box<dyn Error>> {
+    let size = (1200, 300 * quantities.len().max(1) as u32);
+    if Path::new(path).extension().is_some_and(|ext| ext == "svg") {
+        draw_timeseries(SVGBackend::new(path, size).into_drawing_area(), state_keeper, bodies, start, end, quantities, samples)
+    } else {
+        draw_timeseries(BitMapBackend::new(path, size).into_drawing_area(), state_keeper, bodies, start, end, quantities, samples)
+    }
+}
+
+// Press P to plot every quantity for the selected transfer's departure and arrival bodies over the whole propagation
+pub fn plot_transfer_bodies(keys: Res<ButtonInput<KeyCode>>, state_keeper: Res<StateKeeper>) {
+    if !keys.just_pressed(KeyCode::KeyP) {
+        return;
+    }
+    let path = "elements.png";
+    let bodies = [state_keeper.interplanetary_selection.0, state_keeper.interplanetary_selection.1];
+    let (start, end) = (step_to_date(&state_keeper, 0), step_to_date(&state_keeper, state_keeper.last_step_computed));
+    match make_timeseries_plot(&state_keeper, path, &bodies, start, end, &Quantity::ALL, 1000) {
+        Ok(()) => info!("Wrote {}", path),
+        Err(e) => error!("Couldn't plot {}: {}", path, e),
+    }
+}
+
+fn draw_timeseries<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    state_keeper: &StateKeeper,
+    bodies: &[u32],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    quantities: &[Quantity],
+    samples: u32,
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+
+    let first = date_to_step(state_keeper, start).min(state_keeper.last_step_computed);
+    let last = date_to_step(state_keeper, end).min(state_keeper.last_step_computed).max(first);
+    let stride = ((last - first) / samples.max(1)).max(1);
+    let steps: Vec<u32> = (first..=last).step_by(stride as usize).collect();
+    let dates: Vec<DateTime<Utc>> = steps.iter().map(|&step| step_to_date(state_keeper, step)).collect();
+    let bodies: Vec<u32> = bodies.iter().copied().filter(|body| state_keeper.info.get(body).unwrap().kepler_parent != *body).collect();
+
+    for (panel, quantity) in root.split_evenly((quantities.len().max(1), 1)).iter().zip(quantities) {
+        let series: Vec<Vec<f64>> = bodies.iter()
+            .map(|&body| steps.iter().map(|&step| quantity.sample(state_keeper, step, body)).collect())
+            .collect();
+        let (lo, hi) = series.iter().flatten().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &y| (lo.min(y), hi.max(y)));
+        if !lo.is_finite() {
+            continue;
+        }
+        let pad = ((hi - lo) * 0.05).max(hi.abs() * 1e-9).max(1e-12);
+
+        let mut chart = ChartBuilder::on(panel)
+            .margin(10)
+            .x_label_area_size(40)
+            .y_label_area_size(80)
+            .build_cartesian_2d(dates[0]..*dates.last().unwrap(), (lo - pad)..(hi + pad))?;
+        chart
+            .configure_mesh()
+            .x_labels(8)
+            .x_label_formatter(&|date| date.format("%Y-%m-%d").to_string())
+            .y_desc(quantity.label())
+            .draw()?;
+
+        for (index, (&body, values)) in bodies.iter().zip(series).enumerate() {
+            let color = Palette99::pick(index).to_rgba();
+            chart
+                .draw_series(LineSeries::new(dates.iter().copied().zip(values), color.stroke_width(2)))?
+                .label(state_keeper.info.get(&body).unwrap().name.clone())
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
+        }
+        chart
+            .configure_series_labels()
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()?;
+    }
+
+    root.present()?;
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use bevy::prelude::*;
+use bevy_math::DVec3;
+use crate::StateKeeper;
+// Trails: paths built from the propagated history itself rather than an osculating conic, so they show the real,
+// perturbed motion. They're drawn relative to the inertial body, over the scenario's past and future window around the
+// current step, for every body without a conic of its own (or every body, with T pressed).
+
+pub const TRAIL_SAMPLES: u32 = 2000;
+
+// Steps the trail covers, clamped to what's been propagated
+pub fn trail_window(state_keeper: &StateKeeper) -> (u32, u32) {
+    let steps_per_day = 86400.0 / state_keeper.dt;
+    let (past, future) = state_keeper.scenario.trail_days;
+    (
+        state_keeper.current_step.saturating_sub((past * steps_per_day) as u32),
+        (state_keeper.current_step + (future * steps_per_day) as u32).min(state_keeper.last_step_computed),
+    )
+}
+
+// A position at some step as seen from the inertial body at that step. Trails are made of these, and drawn around
+// origin().
+pub fn seen_from_inertial(state_keeper: &StateKeeper, step: u32, position: DVec3) -> DVec3 {
+    position - state_keeper.state.get(&step).unwrap().get(&state_keeper.inertial).unwrap()[0]
+}
+
+// Where paths seen from the inertial body are drawn around: the inertial body at the current step, or the rotating
+// frame's origin when that's on (where paths are drawn in its own coordinates instead)
+pub fn origin(state_keeper: &StateKeeper) -> DVec3 {
+    match state_keeper.synodic {
+        Some(_) => DVec3::ZERO,
+        None => state_keeper.state.get(&state_keeper.current_step).unwrap().get(&state_keeper.inertial).unwrap()[0],
+    }
+}
+
+// A body's path over [first, last] as seen from the inertial body, with up to samples points
+pub fn inertial_trail(state_keeper: &StateKeeper, body: u32, first: u32, last: u32, samples: u32) -> Vec<DVec3> {
+    let stride = ((last - first) / samples.max(1)).max(1);
+    let mut steps: Vec<u32> = (first..=last).step_by(stride as usize).collect();
+    if steps.last() != Some(&last) {
+        steps.push(last);
+    }
+    steps.into_iter()
+        .map(|step| seen_from_inertial(state_keeper, step, state_keeper.state.get(&step).unwrap().get(&body).unwrap()[0]))
+        .collect()
+}
+
+// Whether a body's path is drawn as a trail instead of its osculating conic
+pub fn shows_trail(state_keeper: &StateKeeper, id: u32) -> bool {
+    let info = state_keeper.info.get(&id).unwrap();
+    let conic_shown = info.display_as_keplerian && (id == state_keeper.inertial || info.kepler_parent == state_keeper.inertial);
+    id != state_keeper.inertial && (state_keeper.trails || !conic_shown)
+}
+
+// Press T to draw every body's trail, conics included, and again to go back to conics where there are any
+pub fn toggle_trails(keys: Res<ButtonInput<KeyCode>>, mut state_keeper: ResMut<StateKeeper>) {
+    if keys.just_pressed(KeyCode::KeyT) {
+        state_keeper.trails = !state_keeper.trails;
+    }
+}
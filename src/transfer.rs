@@ -0,0 +1,255 @@
+use bevy::prelude::*;
+use bevy_math::DVec3;
+use big_space::prelude::*;
+use crate::camera::{viewport_position, CameraState};
+use crate::frames::Frame;
+use crate::interplanetary::Interplanetary;
+use crate::keplerian::{position_from_true_anomoly, propagate_rv, rv_from_oe, time_since_periapsis, OE};
+use crate::lines::{empty_line, LineCache, LineSource, View};
+use crate::spheres::sphere_of_influence;
+use crate::trails::{origin, seen_from_inertial};
+use crate::{RootGrid, StateKeeper};
+// The selected transfer, drawn as its three patched conic legs: the departure hyperbola from its periapsis out to the
+// departure body's sphere of influence, the Lambert arc between the spheres, and the arrival hyperbola in to its
+// periapsis. The hyperbolas' periapses are at the departure and arrival steps, so the heliocentric leg is the part of
+// the arc flown after leaving one sphere and before entering the other. A marker follows the spacecraft along them.
+// Like trails, the legs are drawn as seen from the inertial body, or traced out in the rotating frame.
+
+const LEG_SAMPLES: usize = 500;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Leg {
+    Departure,
+    Heliocentric,
+    Arrival,
+}
+
+#[derive(Component)]
+pub struct TransferLegDisplay {
+    pub leg: Leg,
+}
+
+#[derive(Component)]
+pub struct SpacecraftLabel {}
+
+// One of the transfer's hyperbolas, up to where it crosses its body's sphere of influence
+struct Hyperbola {
+    body: u32,
+    mu: f64,
+    oe: OE, // At periapsis
+    f_soi: f64, // True anomaly at the sphere (the hyperbola runs from -f_soi to 0 on arrival, and 0 to f_soi on departure)
+    time: f64, // Between periapsis and the sphere
+}
+
+impl Hyperbola {
+    fn new(state_keeper: &StateKeeper, body: u32, oe: &OE, step: u32) -> Self {
+        let mu = state_keeper.info.get(&body).unwrap().mu;
+        let radius = sphere_of_influence(state_keeper, body, step);
+        // Where r = p / (1 + e cos f) reaches the sphere, or periapsis if the sphere is inside it
+        let f_soi = ((oe.p() / radius - 1.0) / oe.e).clamp(-1.0, 1.0).acos();
+        Hyperbola { body, mu, oe: oe.clone(), f_soi, time: time_since_periapsis(mu, oe.p(), oe.e, f_soi) }
+    }
+
+    // Position relative to the body, t seconds after periapsis
+    fn position(&self, t: f64) -> DVec3 {
+        propagate_rv(self.mu, &rv_from_oe(self.mu, &self.oe), t)[0]
+    }
+}
+
+fn hyperbolas(state_keeper: &StateKeeper, ip: &Interplanetary) -> [Hyperbola; 2] {
+    [
+        Hyperbola::new(state_keeper, ip.body1, &ip.oe1, ip.departure_step),
+        Hyperbola::new(state_keeper, ip.body2, &ip.oe2, ip.arrival_step),
+    ]
+}
+
+fn body_position(state_keeper: &StateKeeper, body: u32, step: u32) -> DVec3 {
+    state_keeper.state.get(&step).unwrap().get(&body).unwrap()[0]
+}
+
+// Where the spacecraft is at a step, in the simulation's frame, or None before departure and after arrival
+pub fn spacecraft_position(state_keeper: &StateKeeper, ip: &Interplanetary, step: u32) -> Option<DVec3> {
+    if step < ip.departure_step || step > ip.arrival_step {
+        return None;
+    }
+    let [departure, arrival] = hyperbolas(state_keeper, ip);
+    let since_departure = (step - ip.departure_step) as f64 * state_keeper.dt;
+    let until_arrival = (ip.arrival_step - step) as f64 * state_keeper.dt;
+    if since_departure <= departure.time {
+        Some(body_position(state_keeper, departure.body, step) + departure.position(since_departure))
+    } else if until_arrival <= arrival.time {
+        Some(body_position(state_keeper, arrival.body, step) + arrival.position(-until_arrival))
+    } else {
+        let mu = state_keeper.info.get(&ip.body0).unwrap().mu;
+        ip.transfer_state(mu, state_keeper.dt, step).map(|state| state[0])
+    }
+}
+
+// A leg's points, each with the step it's flown at (rounded, and within the propagation) and its position in the
+// simulation's frame
+pub fn leg_points(state_keeper: &StateKeeper, ip: &Interplanetary, leg: Leg) -> Vec<(u32, DVec3)> {
+    let [departure, arrival] = hyperbolas(state_keeper, ip);
+    let to_step = |t: f64| (t / state_keeper.dt).round().clamp(0.0, state_keeper.last_step_computed as f64) as u32;
+    let hyperbola_points = |hyperbola: &Hyperbola, periapsis_step: u32, from: f64, to: f64| -> Vec<(u32, DVec3)> {
+        (0..=LEG_SAMPLES)
+            .map(|i| {
+                let f = from + (to - from) * i as f64 / LEG_SAMPLES as f64;
+                let t = time_since_periapsis(hyperbola.mu, hyperbola.oe.p(), hyperbola.oe.e, f);
+                let step = to_step(periapsis_step as f64 * state_keeper.dt + t);
+                (step, body_position(state_keeper, hyperbola.body, step) + position_from_true_anomoly(&hyperbola.oe, hyperbola.oe.f + f))
+            })
+            .collect()
+    };
+    match leg {
+        Leg::Departure => hyperbola_points(&departure, ip.departure_step, 0.0, departure.f_soi),
+        Leg::Arrival => hyperbola_points(&arrival, ip.arrival_step, -arrival.f_soi, 0.0),
+        Leg::Heliocentric => {
+            let mu = state_keeper.info.get(&ip.body0).unwrap().mu;
+            let first = to_step(ip.departure_step as f64 * state_keeper.dt + departure.time);
+            let last = to_step(ip.arrival_step as f64 * state_keeper.dt - arrival.time).max(first);
+            let stride = ((last - first) as usize / LEG_SAMPLES).max(1);
+            let mut steps: Vec<u32> = (first..=last).step_by(stride).collect();
+            if steps.last() != Some(&last) {
+                steps.push(last);
+            }
+            steps.into_iter()
+                .filter_map(|step| ip.transfer_state(mu, state_keeper.dt, step).map(|state| (step, state[0])))
+                .collect()
+        }
+    }
+}
+
+// Where a point of the transfer flown at a step is drawn, relative to trails::origin()
+fn display_point(state_keeper: &StateKeeper, step: u32, position: DVec3) -> DVec3 {
+    match state_keeper.synodic {
+        Some((primary, secondary)) => Frame::Synodic(primary, secondary).transform_from_icrf(state_keeper, step, &[position, DVec3::ZERO])[0],
+        None => seen_from_inertial(state_keeper, step, position),
+    }
+}
+
+// Where the spacecraft is drawn at the current step, if it's in flight
+pub fn spacecraft_display_position(state_keeper: &StateKeeper) -> Option<DVec3> {
+    let step = state_keeper.current_step;
+    let position = spacecraft_position(state_keeper, state_keeper.interplanetary.as_ref()?, step)?;
+    Some(origin(state_keeper) + display_point(state_keeper, step, position))
+}
+
+pub fn spawn_transfer_displays(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    root_grid: Single<Entity, With<RootGrid>>,
+) {
+    let legs = [
+        (Leg::Departure, LinearRgba::new(1.0, 0.6, 0.2, 0.7)),
+        (Leg::Heliocentric, LinearRgba::new(1.0, 1.0, 1.0, 0.7)),
+        (Leg::Arrival, LinearRgba::new(0.2, 0.8, 1.0, 0.7)),
+    ];
+    for (leg, color) in legs {
+        let line = commands.spawn((
+            Mesh3d(meshes.add(empty_line())),
+            MeshMaterial3d(materials.add(StandardMaterial {
+                base_color: Color::srgb(0.5, 0.0, 0.5),
+                emissive: color,
+                unlit: true,
+                ..default()
+            })),
+            Transform::default(),
+            GridCell::<i64>::default(),
+            TransferLegDisplay { leg },
+            LineCache::default(),
+        )).id();
+        commands.entity(*root_grid).add_child(line);
+    }
+
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            display: Display::None,
+            ..default()
+        },
+        Text("+ Spacecraft".to_string()),
+        TextColor(Color::srgb(1.0, 0.6, 0.2)),
+        SpacecraftLabel {},
+    ));
+}
+
+// Redraw the legs of the selected transfer, and move the spacecraft marker to the current step
+pub fn display_transfer(
+    state_keeper: Res<StateKeeper>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    root_grid: Single<&Grid<i64>, With<RootGrid>>,
+    camera: Single<(&Camera, &CameraState)>,
+    mut lines: Query<(&TransferLegDisplay, &Mesh3d, &mut GridCell<i64>, &mut Transform, &mut LineCache)>,
+    label: Single<&mut Node, With<SpacecraftLabel>>,
+) {
+    let (camera, camera_state) = camera.into_inner();
+    let view = View::of(&state_keeper);
+    let source = match &state_keeper.interplanetary {
+        Some(ip) => LineSource::Transfer(ip.departure_step, ip.arrival_step, [ip.oe0.a, ip.oe1.a, ip.oe2.a]),
+        None => LineSource::Empty,
+    };
+    let origin = origin(&state_keeper);
+    for (display, mesh3d, mut grid_cell, mut transform, mut cache) in lines.iter_mut() {
+        if cache.stale(view, source.clone()) {
+            let points: Vec<DVec3> = match &state_keeper.interplanetary {
+                Some(ip) => leg_points(&state_keeper, ip, display.leg).into_iter()
+                    .map(|(step, position)| display_point(&state_keeper, step, position))
+                    .collect(),
+                None => Vec::new(),
+            };
+            cache.rebuild(&mut meshes, &mesh3d.0, &points);
+        }
+        cache.place(&root_grid, origin, &mut grid_cell, &mut transform);
+    }
+
+    let mut label = label.into_inner();
+    let Some(position) = spacecraft_display_position(&state_keeper) else {
+        label.display = Display::None;
+        return;
+    };
+    match viewport_position(camera, camera_state, position) {
+        Some(viewport) => {
+            label.display = Display::DEFAULT;
+            label.top = Val::Px(viewport.y);
+            label.left = Val::Px(viewport.x);
+        }
+        None => label.display = Display::None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interplanetary::interplanetary;
+    use crate::keplerian::position_from_true_anomoly;
+    use crate::spheres::{soi_crossings, sphere_of_influence};
+    use crate::StateKeeper;
+    use super::hyperbolas;
+
+    // The hyperbolas end on their bodies' spheres of influence, f_soi from periapsis and short of the asymptote, and the
+    // spacecraft is seen crossing the spheres where they end
+    #[test]
+    fn hyperbolas_end_at_the_spheres() {
+        let mut app = crate::tests::headless_app_with(crate::tests::transfer_scenario());
+        app.update();
+        let state_keeper = app.world().resource::<StateKeeper>();
+        let (earth, mars) = (state_keeper.interplanetary_selection.0, state_keeper.interplanetary_selection.1);
+        let (departure_step, arrival_step) = crate::tests::TRANSFER_STEPS;
+        let ip = interplanetary(state_keeper, departure_step, arrival_step, earth, mars, true);
+        let crossings = soi_crossings(state_keeper, &ip);
+
+        let [departure, arrival] = hyperbolas(state_keeper, &ip);
+        for (hyperbola, step, sign, crossing) in [(departure, departure_step, 1.0, crossings[0].0), (arrival, arrival_step, -1.0, crossings[1].0)] {
+            let radius = sphere_of_influence(state_keeper, hyperbola.body, step);
+            assert!(hyperbola.f_soi > 0.0 && hyperbola.f_soi < (-1.0 / hyperbola.oe.e).acos());
+            let r = position_from_true_anomoly(&hyperbola.oe, hyperbola.oe.f + sign * hyperbola.f_soi).length();
+            assert!((r / radius - 1.0).abs() < 1e-9, "{} vs {} km", r / 1000.0, radius / 1000.0);
+            let r = hyperbola.position(sign * hyperbola.time).length();
+            assert!((r / radius - 1.0).abs() < 1e-6, "{} vs {} km", r / 1000.0, radius / 1000.0);
+
+            // soi_crossings samples every other step here, and the heliocentric arc it switches to can start just inside
+            let end = step as f64 + sign * hyperbola.time / state_keeper.dt;
+            assert!((crossing as f64 - end).abs() < 3.0, "crossed at step {}, hyperbola ends at {}", crossing, end);
+        }
+    }
+}
@@ -1,6 +1,8 @@
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::input::ButtonState;
 use bevy::prelude::*;
 use crate::*;
-use crate::camera::CameraState;
+use crate::camera::{focus_on, CameraState};
 
 #[derive(Component)]
 pub struct UICamera {}
@@ -8,6 +10,27 @@ pub struct UICamera {}
 #[derive(Component)]
 pub struct BodySelectButton { id: u32 }
 
+// Buttons that move the current step to the selected transfer's departure or arrival
+#[derive(Component)]
+pub enum TimeJumpButton {
+    Departure,
+    Arrival,
+}
+
+// The body browser's search box. While it's active, typing goes into the query instead of the keyboard shortcuts.
+#[derive(Component)]
+pub struct BodySearch {
+    query: String,
+    active: bool,
+}
+
+// A row of the body browser, with the ancestors it's listed under
+#[derive(Component)]
+pub struct BrowserRow {
+    id: u32,
+    ancestors: Vec<u32>,
+}
+
 #[derive(Component)]
 pub struct PorkchopPlot {}
 
@@ -16,10 +39,31 @@ pub struct PorkchopImage {
     pub handle: Handle<Image>
 }
 
+// Bodies in browsing order, depth first from the Sun (or any body without a parent) through kepler_parent, each with
+// its ancestors
+fn body_tree(state_keeper: &StateKeeper) -> Vec<(u32, Vec<u32>)> {
+    let mut ids: Vec<u32> = state_keeper.info.keys().copied().collect();
+    ids.sort();
+    let parent = |id: u32| state_keeper.info.get(&id).unwrap().kepler_parent;
+    let is_root = |id: u32| parent(id) == id || !state_keeper.info.contains_key(&parent(id));
+    let mut rows = Vec::new();
+    let mut stack: Vec<(u32, Vec<u32>)> = ids.iter().rev().filter(|id| is_root(**id)).map(|id| (*id, Vec::new())).collect();
+    while let Some((id, ancestors)) = stack.pop() {
+        let mut lineage = ancestors.clone();
+        lineage.push(id);
+        for child in ids.iter().rev().filter(|child| !is_root(**child) && parent(**child) == id) {
+            stack.push((*child, lineage.clone()));
+        }
+        rows.push((id, ancestors));
+    }
+    rows
+}
+
 pub fn setup_ui(mut commands: Commands, state_keeper: Res<StateKeeper>, mut images: ResMut<Assets<Image>>) {
     let menu_root = commands.spawn((
         Node {
-            bottom: Val::Px(20.0),
+            top: Val::Px(70.0),
+            left: Val::Px(12.0),
             position_type: PositionType::Absolute,
             flex_direction: FlexDirection::Column,
             padding: UiRect::all(Val::Px(5.0)),
@@ -27,35 +71,135 @@ pub fn setup_ui(mut commands: Commands, state_keeper: Res<StateKeeper>, mut imag
         }
         )).id();
 
-    for (id, body_info) in state_keeper.info.iter() {
-        if id.clone() == 0 || id.clone() == 3 || id.clone() == 4 || id.clone() == 9 {
-            let entity = commands.spawn((
-                Button{},
-                Text::new(body_info.name.clone()),
-                BodySelectButton { id: id.clone() }
-            )).id();
+    let search = commands.spawn((
+        Button{},
+        Text::new("Search: "),
+        BodySearch { query: String::new(), active: false },
+    )).id();
+    commands.entity(menu_root).add_child(search);
 
-            commands.entity(menu_root).add_child(entity);
-        }
+    for (id, ancestors) in body_tree(&state_keeper) {
+        let entity = commands.spawn((
+            Button{},
+            Node {
+                padding: UiRect::left(Val::Px(16.0 * ancestors.len() as f32)),
+                ..default()
+            },
+            Text::new(state_keeper.info.get(&id).unwrap().name.clone()),
+            BodySelectButton { id },
+            BrowserRow { id, ancestors },
+        )).id();
+        commands.entity(menu_root).add_child(entity);
     }
+
+    let jumps = commands.spawn(Node {
+        margin: UiRect::top(Val::Px(8.0)),
+        column_gap: Val::Px(12.0),
+        ..default()
+    }).id();
+    for (label, jump) in [("Departure", TimeJumpButton::Departure), ("Arrival", TimeJumpButton::Arrival)] {
+        let entity = commands.spawn((Button{}, Text::new(label), jump)).id();
+        commands.entity(jumps).add_child(entity);
+    }
+    commands.entity(menu_root).add_child(jumps);
 }
 
-pub fn button_interaction(mut state_keeper: ResMut<StateKeeper>, mut button_query: Query<(&Interaction, &BodySelectButton)>, mut camera: Single<(&Camera, &GlobalTransform, &mut CameraState)>) {
-    let (_camera, _camera_global_transform, mut camera_state) = camera.into_inner();
-    for (interaction, button) in button_query.iter_mut() {
+// Clicking a body in the browser focuses (and selects) it, leaving the time alone; the jump buttons move the time only
+pub fn button_interaction(
+    mut state_keeper: ResMut<StateKeeper>,
+    body_buttons: Query<(&Interaction, &BodySelectButton), Changed<Interaction>>,
+    jump_buttons: Query<(&Interaction, &TimeJumpButton), Changed<Interaction>>,
+    mut search: Query<(&Interaction, &mut BodySearch), Changed<Interaction>>,
+    camera: Single<&mut CameraState>,
+) {
+    let mut camera_state = camera.into_inner();
+    for (interaction, button) in body_buttons.iter() {
+        if *interaction == Interaction::Pressed {
+            focus_on(&mut state_keeper, &mut camera_state, button.id);
+            state_keeper.selected = Some(button.id);
+        }
+    }
+    for (interaction, jump) in jump_buttons.iter() {
         if *interaction == Interaction::Pressed {
-            state_keeper.inertial = button.id;
-            camera_state.focused = button.id;
-            if button.id == 3 {
-                state_keeper.current_step = state_keeper.interplanetary_selection.2;
-            } else if button.id == 4 {
-                state_keeper.current_step = state_keeper.interplanetary_selection.3;
-            } else if button.id == 9 {
-                state_keeper.current_step = state_keeper.interplanetary_selection.2;
-            } else if button.id == 0 {
-                state_keeper.current_step = state_keeper.interplanetary_selection.2;
+            state_keeper.current_step = match jump {
+                TimeJumpButton::Departure => state_keeper.interplanetary_selection.2,
+                TimeJumpButton::Arrival => state_keeper.interplanetary_selection.3,
+            };
+        }
+    }
+    for (interaction, mut search) in search.iter_mut() {
+        if *interaction == Interaction::Pressed {
+            search.active = !search.active;
+        }
+    }
+}
+
+// While the search box is active, typed text edits the query (Enter or Escape to finish, Escape also clears it), and
+// the keys are taken from everything else. Runs in PreUpdate, right after input is read.
+pub fn search_input(
+    mut keyboard_events: EventReader<KeyboardInput>,
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    search: Single<&mut BodySearch>,
+) {
+    let mut search = search.into_inner();
+    if !search.active {
+        return;
+    }
+    for event in keyboard_events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        match &event.logical_key {
+            Key::Character(characters) => search.query.push_str(characters),
+            Key::Space => search.query.push(' '),
+            Key::Backspace => {
+                search.query.pop();
             }
-            info!("Selected {}", button.id);
+            Key::Enter => search.active = false,
+            Key::Escape => {
+                search.query.clear();
+                search.active = false;
+            }
+            _ => {}
         }
     }
-}
\ No newline at end of file
+    keys.reset_all();
+}
+
+// Show the rows matching the query (case insensitive), along with the ancestors they're listed under, and highlight
+// the focused body
+pub fn display_browser(
+    state_keeper: Res<StateKeeper>,
+    camera_state: Single<&CameraState>,
+    search: Single<(&BodySearch, &mut Text)>,
+    mut rows: Query<(&BrowserRow, &mut Node, &mut TextColor)>,
+) {
+    let (search, mut search_text) = search.into_inner();
+    let label = format!("Search: {}{}", search.query, if search.active { "_" } else { "" });
+    if search_text.0 != label {
+        search_text.0 = label;
+    }
+
+    let query = search.query.to_lowercase();
+    let matches = |id: u32| state_keeper.info.get(&id).unwrap().name.to_lowercase().contains(&query);
+    let shown: Vec<u32> = rows.iter()
+        .filter(|(row, _, _)| matches(row.id))
+        .flat_map(|(row, _, _)| row.ancestors.iter().copied().chain([row.id]))
+        .collect();
+    for (row, mut node, mut text_color) in rows.iter_mut() {
+        let display = if shown.contains(&row.id) { Display::DEFAULT } else { Display::None };
+        if node.display != display {
+            node.display = display;
+        }
+        let color = if camera_state.focused == row.id {
+            Color::srgb(0.5, 0.8, 1.0)
+        } else if matches(row.id) || query.is_empty() {
+            Color::WHITE
+        } else {
+            Color::srgb(0.5, 0.5, 0.5) // Only listed for the match under it
+        };
+        if text_color.0 != color {
+            text_color.0 = color;
+        }
+    }
+}